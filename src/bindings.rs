@@ -0,0 +1,91 @@
+// Grab-free operation: the keys are bound in sway config to `nop alttab ...` commands, which
+// sway reports as binding events, so no input device access is needed. The modifier release
+// can only be bound in a binding mode, which is entered for the duration of the sequence.
+
+use std::error::Error;
+
+use crate::WorkspaceSwitcherEvent;
+
+// The binding mode the release and in-sequence bindings are defined in
+pub const BINDING_MODE: &str = "alttab";
+const NOP_COMMAND: &str = "nop alttab";
+
+pub struct BindingRouter {
+    sway_ipc: swayipc::Connection,
+    in_sequence: bool,
+}
+
+impl BindingRouter {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            sway_ipc: crate::connect_ipc()?,
+            in_sequence: false,
+        })
+    }
+
+    // Converts the binding to a switcher event, entering or leaving the binding mode on the
+    // sequence's start and end. Bindings with other commands are None.
+    pub fn route(&mut self, binding: &swayipc::BindingEvent) -> Option<WorkspaceSwitcherEvent> {
+        let command = binding.binding.command.trim();
+        let action = command.strip_prefix(NOP_COMMAND)?;
+        if !action.is_empty() && !action.starts_with(' ') {
+            return None;
+        }
+        let evt = match action.trim() {
+            "" => WorkspaceSwitcherEvent::Trigger,
+            "back" => WorkspaceSwitcherEvent::TriggerBack,
+            "move" => WorkspaceSwitcherEvent::TriggerMove,
+            "move back" => WorkspaceSwitcherEvent::TriggerMoveBack,
+            "window" => WorkspaceSwitcherEvent::WindowTrigger,
+            "window back" => WorkspaceSwitcherEvent::WindowTriggerBack,
+            "end" => WorkspaceSwitcherEvent::EndMod,
+            "cancel" => WorkspaceSwitcherEvent::Cancel,
+            "pin" => WorkspaceSwitcherEvent::PinCurrent,
+            "remove" => WorkspaceSwitcherEvent::RemoveCurrent,
+            jump if jump.starts_with("jump ") => match jump["jump ".len()..].trim().parse() {
+                Ok(n) => WorkspaceSwitcherEvent::JumpTo(n),
+                Err(_) => {
+                    log::warn!("Invalid position in binding command \"{command}\"");
+                    return None;
+                }
+            },
+            _ => {
+                log::warn!("Unknown binding command \"{command}\"");
+                return None;
+            }
+        };
+
+        match evt {
+            WorkspaceSwitcherEvent::Trigger
+            | WorkspaceSwitcherEvent::TriggerBack
+            | WorkspaceSwitcherEvent::TriggerMove
+            | WorkspaceSwitcherEvent::TriggerMoveBack
+            | WorkspaceSwitcherEvent::WindowTrigger
+            | WorkspaceSwitcherEvent::WindowTriggerBack
+                if !self.in_sequence =>
+            {
+                self.in_sequence = true;
+                self.set_mode(BINDING_MODE);
+            }
+            WorkspaceSwitcherEvent::EndMod
+            | WorkspaceSwitcherEvent::Cancel
+            | WorkspaceSwitcherEvent::JumpTo(_) => {
+                self.in_sequence = false;
+                self.set_mode("default");
+            }
+            _ => {}
+        }
+        Some(evt)
+    }
+
+    fn set_mode(&mut self, mode: &str) {
+        let result = self.sway_ipc.run_command(format!("mode {mode}"));
+        match result.map(|outcomes| outcomes.into_iter().find_map(Result::err)) {
+            Ok(None) => {}
+            Ok(Some(e)) => log::error!(
+                "Can't switch to the \"{mode}\" binding mode, is it defined in sway config? {e}"
+            ),
+            Err(e) => log::error!("Can't switch to the \"{mode}\" binding mode: {e}"),
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use std::time::Instant;
+
+// The source of time for the timing features, so it can be substituted where the real clock
+// gets in the way (e.g. to advance time deterministically)
+pub trait Clock: Send {
+    fn now(&self) -> Instant;
+}
+
+// The real monotonic clock
+pub struct MonotonicClock;
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+// A clock which only moves when advanced, shared by its clones
+#[cfg(test)]
+#[derive(Clone)]
+pub struct ManualClock(std::sync::Arc<std::sync::Mutex<Instant>>);
+
+#[cfg(test)]
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self(std::sync::Arc::new(std::sync::Mutex::new(Instant::now())))
+    }
+}
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn advance(&self, duration: std::time::Duration) {
+        *self.0.lock().unwrap() += duration;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+}
@@ -0,0 +1,373 @@
+use std::error::Error;
+use std::ffi::CString;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+
+use evdev_rs::enums::{EventCode, EventType, EV_KEY};
+use evdev_rs::{Device, DeviceWrapper, EventCodeIterator};
+
+const INPUT_DIR: &str = "/dev/input";
+// udev creates the symlinks there a bit after the device node
+const SYMLINK_DIRS: [&str; 2] = ["/dev/input/by-id", "/dev/input/by-path"];
+
+// Stable criteria to find an input device by, unlike /dev/input/eventN numbers which
+// change across reboots and hotplugs. Unset fields match any device. The name and the paths
+// are glob patterns, where `*` matches any number of characters and `?` any single one.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceMatcher {
+    pub name: Option<String>,
+    pub vendor: Option<u16>,
+    pub product: Option<u16>,
+    // The device is reachable by any of these paths (e.g. /dev/input/by-id symlinks), the
+    // relative ones are in /dev/input. Only the file name part can have wildcards.
+    pub paths: Vec<String>,
+}
+
+impl DeviceMatcher {
+    // Criteria identifying the same keyboard when it's plugged in again
+    pub fn identity_of(device: &Device) -> Self {
+        Self {
+            name: device.name().map(str::to_string),
+            vendor: Some(device.vendor_id()),
+            product: Some(device.product_id()),
+            paths: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none()
+            && self.vendor.is_none()
+            && self.product.is_none()
+            && self.paths.is_empty()
+    }
+
+    pub fn matches(&self, path: &Path, device: &Device) -> bool {
+        self.name
+            .as_deref()
+            .is_none_or(|name| device.name().is_some_and(|n| glob_match(name, n)))
+            && self.vendor.is_none_or(|v| device.vendor_id() == v)
+            && self.product.is_none_or(|p| device.product_id() == p)
+            && (self.paths.is_empty() || self.path_matches(path))
+    }
+
+    fn path_matches(&self, path: &Path) -> bool {
+        let Ok(path) = std::fs::canonicalize(path) else {
+            return false;
+        };
+        self.paths
+            .iter()
+            .flat_map(|pattern| expand_path_pattern(pattern))
+            .any(|candidate| std::fs::canonicalize(candidate).is_ok_and(|c| c == path))
+    }
+
+    // The given paths and the devices matching the criteria, without duplicates
+    pub fn resolve(&self, paths: &[PathBuf]) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        let mut resolved = paths.to_vec();
+        if self.is_empty() {
+            return Ok(resolved);
+        }
+        let known = paths
+            .iter()
+            .filter_map(|path| std::fs::canonicalize(path).ok())
+            .collect::<Vec<_>>();
+        for path in self.find()? {
+            if !known.contains(&path) {
+                resolved.push(path);
+            }
+        }
+        Ok(resolved)
+    }
+
+    // Resolves the criteria to the current /dev/input/eventN paths: all the devices matching
+    // the path patterns, or the first device matching the rest of the criteria
+    pub fn find(&self) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        let mut found = Vec::new();
+        for path in event_device_paths()? {
+            let device = match Device::new_from_path(&path) {
+                Ok(device) => device,
+                Err(e) => {
+                    log::debug!("Skipping {}: {e}", path.display());
+                    continue;
+                }
+            };
+            if self.matches(&path, &device) {
+                log::info!(
+                    "Using input device {} (\"{}\")",
+                    path.display(),
+                    device.name().unwrap_or("")
+                );
+                found.push(path);
+                if self.paths.is_empty() {
+                    break;
+                }
+            }
+        }
+        match found.is_empty() {
+            true => Err(format!("no input device matches {self:?}").into()),
+            false => Ok(found),
+        }
+    }
+}
+
+pub fn is_pattern(text: &str) -> bool {
+    text.contains(['*', '?'])
+}
+
+// Matches the whole text against the pattern with `*` and `?` wildcards
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let (pattern, text) = (pattern.as_bytes(), text.as_bytes());
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` is in the pattern, and the text position it has been tried up to
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Let the `*` match one more character
+                Some((star, star_t)) => {
+                    backtrack = Some((star, star_t + 1));
+                    p = star + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+// The existing paths matching the pattern, relative ones are in /dev/input
+fn expand_path_pattern(pattern: &str) -> Vec<PathBuf> {
+    let pattern = Path::new(INPUT_DIR).join(pattern);
+    let (Some(dir), Some(file_pattern)) = (pattern.parent(), pattern.file_name()) else {
+        return Vec::new();
+    };
+    let file_pattern = file_pattern.to_string_lossy();
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| glob_match(&file_pattern, &entry.file_name().to_string_lossy()))
+        .map(|entry| entry.path())
+        .collect()
+}
+
+// Picks the keyboard to intercept when none is given. The candidates having any of the keys
+// are ranked by how many of them they have, then real devices go before virtual ones (e.g.
+// the output device of another instance, which copies the real keyboard), then by the number
+// of keys. The ranking is logged to help picking another one.
+pub fn detect_keyboard(keys: &[EV_KEY]) -> Result<PathBuf, Box<dyn Error>> {
+    let mut candidates = Vec::new();
+    for path in event_device_paths()? {
+        let device = match Device::new_from_path(&path) {
+            Ok(device) => device,
+            Err(e) => {
+                log::debug!("Skipping {}: {e}", path.display());
+                continue;
+            }
+        };
+        let has_key = |key: &EV_KEY| device.has_event_code(&EventCode::EV_KEY(*key));
+        let configured = keys.iter().filter(|key| has_key(key)).count();
+        if configured == 0 {
+            continue;
+        }
+        let key_count = EventCodeIterator::new(&EventType::EV_KEY)
+            .filter(|code| device.has_event_code(code))
+            .count();
+        let name = device.name().unwrap_or("").to_string();
+        let real = !is_virtual(&path);
+        candidates.push(((configured, real, key_count), path, name));
+    }
+    // Stable, so on a tie the lower eventN wins
+    candidates.sort_by(|(a, _, _), (b, _, _)| b.cmp(a));
+
+    log::info!("Detected keyboards, the first one is used (pass the path to use another):");
+    for ((configured, real, key_count), path, name) in &candidates {
+        log::info!(
+            "    {} (\"{name}\"): {configured} of {} configured keys, {key_count} keys{}",
+            path.display(),
+            keys.len(),
+            if *real { "" } else { ", virtual" }
+        );
+    }
+    match candidates.into_iter().next() {
+        Some((_, path, _)) => Ok(path),
+        None => Err("no keyboard with the configured keys found, pass its path".into()),
+    }
+}
+
+// Virtual devices (uinput ones, like ours) live under /sys/devices/virtual
+fn is_virtual(path: &Path) -> bool {
+    let Some(file_name) = path.file_name() else {
+        return false;
+    };
+    std::fs::canonicalize(Path::new("/sys/class/input").join(file_name))
+        .is_ok_and(|sys_path| sys_path.starts_with("/sys/devices/virtual"))
+}
+
+// Watches /dev/input for event devices being plugged in
+pub struct DeviceWatcher {
+    inotify: OwnedFd,
+    // Watch descriptors of the symlink directories
+    symlink_watches: Vec<(libc::c_int, PathBuf)>,
+}
+
+impl DeviceWatcher {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            let e = std::io::Error::last_os_error();
+            return Err(format!("can't initialize inotify: {e}").into());
+        }
+        let inotify = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // The device node is created by the kernel accessible only by root, udev sets up the
+        // permissions a bit later, so attribute changes are watched too
+        let dir = CString::new(INPUT_DIR).expect("no nul bytes in the path");
+        let mask = libc::IN_CREATE | libc::IN_ATTRIB;
+        if unsafe { libc::inotify_add_watch(inotify.as_raw_fd(), dir.as_ptr(), mask) } < 0 {
+            let e = std::io::Error::last_os_error();
+            return Err(format!("can't watch {INPUT_DIR}: {e}").into());
+        }
+        // For the devices given by symlink patterns, which may not match before the symlink
+        // appears. There may be no such directories until a device is plugged in, that's fine.
+        let symlink_watches = SYMLINK_DIRS
+            .into_iter()
+            .filter_map(|dir| {
+                let path = CString::new(dir).expect("no nul bytes in the path");
+                let wd = unsafe {
+                    libc::inotify_add_watch(inotify.as_raw_fd(), path.as_ptr(), libc::IN_CREATE)
+                };
+                (wd >= 0).then(|| (wd, PathBuf::from(dir)))
+            })
+            .collect();
+        Ok(Self {
+            inotify,
+            symlink_watches,
+        })
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.inotify.as_raw_fd()
+    }
+
+    // Drains the pending notifications, returns the event device paths which have appeared
+    // or changed their permissions since the last call
+    pub fn changed_paths(&self) -> Vec<PathBuf> {
+        const HEADER_SIZE: usize = std::mem::size_of::<libc::inotify_event>();
+        let mut buf = [0u8; 4096];
+        let mut paths = Vec::new();
+        loop {
+            let len = unsafe { libc::read(self.fd(), buf.as_mut_ptr().cast(), buf.len()) };
+            if len <= 0 {
+                break;
+            }
+            let mut offset = 0;
+            while offset + HEADER_SIZE <= len as usize {
+                let event: libc::inotify_event =
+                    unsafe { std::ptr::read_unaligned(buf[offset..].as_ptr().cast()) };
+                let name = &buf[offset + HEADER_SIZE..][..event.len as usize];
+                let name = name.split(|&b| b == 0).next().unwrap_or_default();
+                let name = String::from_utf8_lossy(name);
+                // A new symlink stands for the device it points to
+                let path = match self.symlink_watches.iter().find(|(wd, _)| *wd == event.wd) {
+                    Some((_, dir)) => {
+                        std::fs::canonicalize(dir.join(name.as_ref())).unwrap_or_default()
+                    }
+                    None => Path::new(INPUT_DIR).join(name.as_ref()),
+                };
+                if event_number(&path).is_some() && !paths.contains(&path) {
+                    paths.push(path);
+                }
+                offset += HEADER_SIZE + event.len as usize;
+            }
+        }
+        paths
+    }
+}
+
+// All /dev/input/eventN paths sorted by N
+pub fn event_device_paths() -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut paths = std::fs::read_dir(INPUT_DIR)
+        .map_err(|e| format!("can't list {INPUT_DIR}: {e}"))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter_map(|path| event_number(&path).map(|n| (n, path)))
+        .collect::<Vec<_>>();
+    paths.sort();
+    Ok(paths.into_iter().map(|(_, path)| path).collect())
+}
+
+fn event_number(path: &Path) -> Option<u32> {
+    path.file_name()?
+        .to_str()?
+        .strip_prefix("event")?
+        .parse()
+        .ok()
+}
+
+// Prints every event device with what's needed to pick the right one for the arguments
+pub fn list_devices() -> Result<(), Box<dyn Error>> {
+    for path in event_device_paths()? {
+        let device = match Device::new_from_path(&path) {
+            Ok(device) => device,
+            Err(e) => {
+                println!("{}: can't open: {e}\n", path.display());
+                continue;
+            }
+        };
+        let capabilities = EventType::EV_SYN
+            .iter()
+            .filter(|ev_type| !matches!(ev_type, EventType::EV_SYN | EventType::EV_MAX))
+            .filter(|ev_type| device.has_event_type(ev_type))
+            .map(|ev_type| ev_type.to_string())
+            .collect::<Vec<_>>();
+        println!(
+            "{}: \"{}\"{}",
+            path.display(),
+            device.name().unwrap_or(""),
+            if looks_like_keyboard(&device) {
+                " (keyboard)"
+            } else {
+                ""
+            }
+        );
+        println!("    phys: {}", device.phys().unwrap_or("none"));
+        println!(
+            "    vendor: {:04x}, product: {:04x}",
+            device.vendor_id(),
+            device.product_id()
+        );
+        println!("    capabilities: {}\n", capabilities.join(" "));
+    }
+    Ok(())
+}
+
+// Power buttons, mice with extra buttons and the like report some keys too, a keyboard has
+// the letters and the shortcut keys
+fn looks_like_keyboard(device: &Device) -> bool {
+    [
+        EV_KEY::KEY_A,
+        EV_KEY::KEY_Z,
+        EV_KEY::KEY_TAB,
+        EV_KEY::KEY_LEFTMETA,
+    ]
+    .into_iter()
+    .all(|key| device.has_event_code(&EventCode::EV_KEY(key)))
+}
+
+// USB-style hexadecimal vendor/product id, with or without the 0x prefix
+pub fn parse_usb_id(id: &str) -> Result<u16, String> {
+    let digits = id
+        .strip_prefix("0x")
+        .or_else(|| id.strip_prefix("0X"))
+        .unwrap_or(id);
+    u16::from_str_radix(digits, 16).map_err(|e| format!("invalid hexadecimal id: {e}"))
+}
@@ -0,0 +1,217 @@
+use std::path::{Path, PathBuf};
+
+use evdev_rs::enums::EV_KEY;
+use evdev_rs::{Device, DeviceWrapper, GrabMode};
+
+use crate::devices::DeviceMatcher;
+use crate::interceptor::KeyConfig;
+use crate::output::OutputBackend;
+use crate::virtual_keyboard::VirtualKeyboard;
+
+// Physical keys whose meaning is changed by well-known XKB options. The evdev device still
+// reports the physical key, since sway applies the keymap after us.
+const XKB_OPTION_KEYS: &[(&str, &[EV_KEY])] = &[
+    ("caps:", &[EV_KEY::KEY_CAPSLOCK]),
+    ("ctrl:nocaps", &[EV_KEY::KEY_CAPSLOCK]),
+    (
+        "ctrl:swapcaps",
+        &[EV_KEY::KEY_CAPSLOCK, EV_KEY::KEY_LEFTCTRL],
+    ),
+    ("ctrl:ac_ctrl", &[EV_KEY::KEY_A]),
+    ("ctrl:menu_rctrl", &[EV_KEY::KEY_COMPOSE]),
+    ("ctrl:rctrl_ralt", &[EV_KEY::KEY_RIGHTCTRL]),
+    (
+        "ctrl:swap_lalt_lctl",
+        &[EV_KEY::KEY_LEFTALT, EV_KEY::KEY_LEFTCTRL],
+    ),
+    (
+        "ctrl:swap_lwin_lctl",
+        &[EV_KEY::KEY_LEFTMETA, EV_KEY::KEY_LEFTCTRL],
+    ),
+    (
+        "ctrl:swap_rwin_rctl",
+        &[EV_KEY::KEY_RIGHTMETA, EV_KEY::KEY_RIGHTCTRL],
+    ),
+    (
+        "ctrl:swap_ralt_rctl",
+        &[EV_KEY::KEY_RIGHTALT, EV_KEY::KEY_RIGHTCTRL],
+    ),
+    (
+        "altwin:",
+        &[
+            EV_KEY::KEY_LEFTALT,
+            EV_KEY::KEY_RIGHTALT,
+            EV_KEY::KEY_LEFTMETA,
+            EV_KEY::KEY_RIGHTMETA,
+        ],
+    ),
+    ("lv3:ralt", &[EV_KEY::KEY_RIGHTALT]),
+    ("lv3:lalt", &[EV_KEY::KEY_LEFTALT]),
+    ("lv3:lwin", &[EV_KEY::KEY_LEFTMETA]),
+    ("lv3:rwin", &[EV_KEY::KEY_RIGHTMETA]),
+    ("lv3:caps", &[EV_KEY::KEY_CAPSLOCK]),
+    ("lv3:menu", &[EV_KEY::KEY_COMPOSE]),
+    ("compose:ralt", &[EV_KEY::KEY_RIGHTALT]),
+    ("compose:lwin", &[EV_KEY::KEY_LEFTMETA]),
+    ("compose:rwin", &[EV_KEY::KEY_RIGHTMETA]),
+    ("compose:caps", &[EV_KEY::KEY_CAPSLOCK]),
+    ("compose:menu", &[EV_KEY::KEY_COMPOSE]),
+];
+
+// Collects xkb_options values from the sway config text, e.g. from
+// `input type:keyboard xkb_options ctrl:nocaps,altwin:swap_alt_win`
+fn xkb_options(config: &str) -> Vec<&str> {
+    crate::xkb::config_values(config, "xkb_options")
+        .into_iter()
+        .flat_map(|options| options.split(','))
+        .filter(|option| !option.is_empty())
+        .collect()
+}
+
+// Warns about configured keys which sway remaps to something else with XKB options, since
+// the key configuration is about physical keys and it may be not what the user expects
+pub fn check_sway_remaps(sway_ipc: &mut swayipc::Connection, keys: &[EV_KEY]) {
+    let config = match sway_ipc.get_config() {
+        Ok(config) => config.config,
+        Err(e) => {
+            log::debug!("Can't get sway config to check for key remaps: {e}");
+            return;
+        }
+    };
+
+    for option in xkb_options(&config) {
+        let remapped = XKB_OPTION_KEYS
+            .iter()
+            .filter(|(prefix, _)| option.starts_with(prefix))
+            .flat_map(|(_, remapped)| remapped.iter())
+            .filter(|key| keys.contains(key));
+        for key in remapped {
+            log::warn!(
+                "{:?} is remapped by sway (xkb_options {option}), the key configuration \
+                refers to physical keys as reported by the keyboard, not to the remapped ones",
+                key
+            );
+        }
+    }
+}
+
+// Reports the outcome of a single --check item, returns whether it has passed
+fn report(what: &str, result: Result<String, String>, hint: &str) -> bool {
+    match result {
+        Ok(details) => {
+            println!("[ OK ] {what}: {details}");
+            true
+        }
+        Err(e) => {
+            println!("[FAIL] {what}: {e}");
+            println!("       {hint}");
+            false
+        }
+    }
+}
+
+fn check_input_device(path: &Path) -> Result<String, String> {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .map_err(|e| format!("can't open {}: {e}", path.display()))?;
+    let mut device = Device::new_from_file(file)
+        .map_err(|e| format!("can't create an evdev device from {}: {e}", path.display()))?;
+    device
+        .grab(GrabMode::Grab)
+        .map_err(|e| format!("can't grab {}: {e}", path.display()))?;
+    device
+        .grab(GrabMode::Ungrab)
+        .map_err(|e| format!("can't ungrab {}: {e}", path.display()))?;
+    Ok(format!(
+        "{} ({})",
+        path.display(),
+        device.name().unwrap_or("unnamed")
+    ))
+}
+
+fn check_input_devices(
+    paths: &[PathBuf],
+    matcher: &DeviceMatcher,
+    pointer_devices: &[PathBuf],
+) -> Result<String, String> {
+    if paths.is_empty() && matcher.is_empty() {
+        return Err("no input device is given".to_string());
+    }
+    let mut paths = matcher.resolve(paths).map_err(|e| e.to_string())?;
+    paths.extend_from_slice(pointer_devices);
+    paths
+        .iter()
+        .map(|path| check_input_device(path))
+        .collect::<Result<Vec<_>, _>>()
+        .map(|devices| devices.join(", "))
+}
+
+fn check_uinput() -> Result<String, String> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open("/dev/uinput")
+        .map(|_| "/dev/uinput is writable".to_string())
+        .map_err(|e| format!("can't open /dev/uinput: {e}"))
+}
+
+fn check_sway_ipc(keys: &[EV_KEY]) -> Result<String, String> {
+    let mut conn = crate::connect_ipc().map_err(|e| e.to_string())?;
+    let version = conn
+        .get_version()
+        .map_err(|e| format!("can't get the version: {e}"))?;
+    check_sway_remaps(&mut conn, keys);
+    Ok(version.human_readable)
+}
+
+// Validates everything needed to run: the key configuration, the input device, the output and the
+// sway IPC socket, printing a checklist to stdout. Returns whether everything is fine.
+pub fn run_checks(
+    input_devices: &[PathBuf],
+    matcher: &DeviceMatcher,
+    pointer_devices: &[PathBuf],
+    key_configs: &[&KeyConfig],
+    keys: &[EV_KEY],
+    output: OutputBackend,
+) -> bool {
+    let results = [
+        report(
+            "Key configuration",
+            key_configs
+                .iter()
+                .try_for_each(|key_config| key_config.validate())
+                .map(|_| format!("{keys:?}"))
+                .map_err(|e| e.to_string()),
+            "Pick a trigger key which is not one of the modifiers",
+        ),
+        report(
+            "Input devices",
+            check_input_devices(input_devices, matcher, pointer_devices),
+            "Check the device path or selectors, and that you're in the \"input\" group \
+            (or running as root)",
+        ),
+        match output {
+            OutputBackend::Uinput => report(
+                "uinput",
+                check_uinput(),
+                "Load the module with `modprobe uinput` and allow access to /dev/uinput \
+                with a udev rule",
+            ),
+            OutputBackend::Wayland => report(
+                "Wayland virtual keyboard",
+                VirtualKeyboard::connect()
+                    .map(|_| "created".to_string())
+                    .map_err(|e| e.to_string()),
+                "Make sure WAYLAND_DISPLAY is set and the compositor supports \
+                zwp_virtual_keyboard_manager_v1",
+            ),
+        },
+        report(
+            "Sway IPC",
+            check_sway_ipc(keys),
+            "Make sure sway is running and SWAYSOCK points to its socket",
+        ),
+    ];
+    results.iter().all(|&passed| passed)
+}
@@ -0,0 +1,131 @@
+// Horizontal three-finger swipes on a touchpad as an alternative to the shortcut: each swipe
+// step to the right selects the next workspace, to the left - the previous one, and lifting
+// the fingers commits the selection. The touchpad isn't grabbed, the pointer keeps working.
+
+use std::error::Error;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+
+use evdev_rs::enums::{EventCode, EV_ABS, EV_KEY};
+use evdev_rs::{Device, DeviceWrapper, ReadFlag, ReadStatus};
+
+use crate::WorkspaceSwitcherEvent;
+
+// The finger travel selecting the next workspace, in millimeters
+const STEP_MM: i32 = 20;
+// Used for the touchpads which don't report their resolution
+const STEPS_PER_WIDTH: i32 = 6;
+
+pub struct GestureSource {
+    device: Device,
+    evt_tx: Sender<WorkspaceSwitcherEvent>,
+    step: i32,
+    three_fingers: bool,
+    last_x: Option<i32>,
+    // The travel since the last step, towards the next one in either direction
+    travel: i32,
+    triggered: bool,
+}
+
+impl GestureSource {
+    pub fn open(
+        path: &Path,
+        evt_tx: Sender<WorkspaceSwitcherEvent>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let file = std::fs::File::open(path)
+            .map_err(|e| format!("can't open the touchpad ({}): {e}", path.display()))?;
+        let device = Device::new_from_file(file)
+            .map_err(|e| format!("can't create libevdev touchpad device: {e}"))?;
+        if !device.has_event_code(&EventCode::EV_KEY(EV_KEY::BTN_TOOL_TRIPLETAP)) {
+            return Err(format!("{} can't detect three fingers", path.display()).into());
+        }
+        let x_axis = device
+            .abs_info(&EventCode::EV_ABS(EV_ABS::ABS_X))
+            .ok_or_else(|| format!("{} has no X axis", path.display()))?;
+        let step = match x_axis.resolution {
+            0 => (x_axis.maximum - x_axis.minimum) / STEPS_PER_WIDTH,
+            resolution => resolution * STEP_MM,
+        };
+
+        log::debug!(
+            "Touchpad for gestures: {} (\"{}\"), step: {step}",
+            path.display(),
+            device.name().unwrap_or("")
+        );
+        Ok(Self {
+            device,
+            evt_tx,
+            step: step.max(1),
+            three_fingers: false,
+            last_x: None,
+            travel: 0,
+            triggered: false,
+        })
+    }
+
+    pub fn run(&mut self) {
+        log::info!("Starting the gesture recognizer...");
+
+        loop {
+            match self
+                .device
+                .next_event(ReadFlag::NORMAL | ReadFlag::BLOCKING)
+            {
+                Ok((ReadStatus::Success, ev)) => self.on_event(ev.event_code, ev.value),
+                // A gesture may be lost, start over from the current state
+                Ok((ReadStatus::Sync, _)) => {
+                    self.end_swipe();
+                    self.three_fingers = false;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                // E.g. the touchpad has been unplugged, the keyboard shortcut still works
+                Err(e) => {
+                    log::error!("Can't read from the touchpad, the gestures are off: {e}");
+                    self.end_swipe();
+                    return;
+                }
+            }
+        }
+    }
+
+    fn on_event(&mut self, code: EventCode, value: i32) {
+        match code {
+            EventCode::EV_KEY(EV_KEY::BTN_TOOL_TRIPLETAP) => {
+                self.three_fingers = value == 1;
+                if !self.three_fingers {
+                    self.end_swipe();
+                }
+            }
+            EventCode::EV_ABS(EV_ABS::ABS_X) if self.three_fingers => {
+                if let Some(last_x) = self.last_x.replace(value) {
+                    self.travel += value - last_x;
+                }
+                while self.travel.abs() >= self.step {
+                    let (evt, step) = match self.travel > 0 {
+                        true => (WorkspaceSwitcherEvent::Trigger, self.step),
+                        false => (WorkspaceSwitcherEvent::TriggerBack, -self.step),
+                    };
+                    self.travel -= step;
+                    self.triggered = true;
+                    self.send(evt);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn end_swipe(&mut self) {
+        if self.triggered {
+            self.send(WorkspaceSwitcherEvent::EndMod);
+        }
+        self.last_x = None;
+        self.travel = 0;
+        self.triggered = false;
+    }
+
+    fn send(&self, evt: WorkspaceSwitcherEvent) {
+        self.evt_tx
+            .send(evt)
+            .expect("can't send a gesture event, channel is dead");
+    }
+}
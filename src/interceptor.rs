@@ -1,135 +1,1449 @@
 use std::error::Error;
 use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
 
-use evdev_rs::enums::EventCode::EV_KEY;
-use evdev_rs::{Device, InputEvent, ReadFlag, ReadStatus, UInputDevice};
+use evdev_rs::enums::EventCode::{self, EV_KEY};
+use evdev_rs::enums::{EV_MSC, EV_REL, EV_SYN};
+use evdev_rs::{InputEvent, TimeVal};
 
+use super::clock::Clock;
+use super::output::{EventSink, OutputBackend, OutputConfig};
+use super::signals::{self, Request};
+use super::source::{EventSource, GrabPolicy};
+use super::virtual_keyboard::VirtualKeyboard;
 use super::WorkspaceSwitcherEvent;
 
-pub struct KeyConfig {
-    // To avoid searching in Vec<EV_KEY>, there is one required modifier and one optional
-    // Guess it helps with performance (remember, we're filtering realtime keyboard events)
-    pub modifier1: evdev_rs::enums::EV_KEY,
-    pub modifier2: Option<evdev_rs::enums::EV_KEY>,
+// The keys sending JumpTo(1) to JumpTo(9)
+const JUMP_KEYS: [evdev_rs::enums::EV_KEY; 9] = {
+    use evdev_rs::enums::EV_KEY::*;
+    [
+        KEY_1, KEY_2, KEY_3, KEY_4, KEY_5, KEY_6, KEY_7, KEY_8, KEY_9,
+    ]
+};
+
+// Modifier keys which have to be held all together and a trigger, e.g.
+// KEY_LEFTCTRL+KEY_LEFTALT,KEY_TAB
+#[derive(Clone, Debug)]
+pub struct Chord {
+    pub modifiers: Vec<evdev_rs::enums::EV_KEY>,
     pub trigger: evdev_rs::enums::EV_KEY,
 }
 
+impl std::str::FromStr for Chord {
+    type Err = String;
+
+    fn from_str(chord: &str) -> Result<Self, Self::Err> {
+        let (modifiers, trigger) = chord
+            .split_once(',')
+            .ok_or("expected modifiers joined with '+', a comma and a trigger")?;
+        let parse = |key: &str| crate::parse_keycode(key).map_err(|e| format!("{key}: {e}"));
+        let modifiers = modifiers.split('+').map(parse).collect::<Result<_, _>>()?;
+        Ok(Self {
+            modifiers,
+            trigger: parse(trigger)?,
+        })
+    }
+}
+
+// An input device with its own chord instead of the main shortcut, e.g.
+// /dev/input/event3=KEY_CAPSLOCK,KEY_TAB
+#[derive(Clone, Debug)]
+pub struct DeviceChord {
+    pub path: std::path::PathBuf,
+    pub chord: Chord,
+}
+
+impl std::str::FromStr for DeviceChord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, chord) = s.split_once('=').ok_or("expected PATH=CHORD")?;
+        Ok(Self {
+            path: path.into(),
+            chord: chord.parse()?,
+        })
+    }
+}
+
+// A hardware scancode (MSC_SCAN) which stands for a key in the chord detection, whatever key
+// code it comes with, e.g. 0x700e3=KEY_LEFTMETA. The forwarded events keep their key codes.
+#[derive(Clone, Copy, Debug)]
+pub struct ScancodeKey {
+    pub scancode: i32,
+    pub key: evdev_rs::enums::EV_KEY,
+}
+
+impl std::str::FromStr for ScancodeKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scancode, key) = s.split_once('=').ok_or("expected SCANCODE=KEY")?;
+        let scancode = match scancode.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => scancode.parse(),
+        }
+        .map_err(|e| format!("invalid scancode {scancode}: {e}"))?;
+        Ok(Self {
+            // The kernel reports the scancodes as the event's signed value
+            scancode: scancode as i32,
+            key: crate::parse_keycode(key).map_err(|e| format!("{key}: {e}"))?,
+        })
+    }
+}
+
+// What a binding does when its chord is pressed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum BindingAction {
+    // Select the next (or the previous) workspace, the reverse modifier flips the direction
+    CycleForward,
+    CycleBack,
+    // The same for the windows of the focused workspace
+    WindowForward,
+    WindowBack,
+    // Switch to the previous workspace at once, without waiting for the modifier release
+    JumpToLast,
+    // Go back to where the sequence has started and end it
+    Cancel,
+}
+
+impl BindingAction {
+    // Whether holding the trigger down repeats the action
+    fn repeats(self) -> bool {
+        matches!(
+            self,
+            Self::CycleForward | Self::CycleBack | Self::WindowForward | Self::WindowBack
+        )
+    }
+}
+
+// A chord mapped to an action, e.g. KEY_LEFTMETA,KEY_GRAVE=window-forward
+#[derive(Clone, Debug)]
+pub struct Binding {
+    pub modifiers: Vec<evdev_rs::enums::EV_KEY>,
+    pub key: evdev_rs::enums::EV_KEY,
+    pub action: BindingAction,
+}
+
+impl std::str::FromStr for Binding {
+    type Err = String;
+
+    fn from_str(binding: &str) -> Result<Self, Self::Err> {
+        let (chord, action) = binding
+            .rsplit_once('=')
+            .ok_or("expected a chord, '=' and an action")?;
+        let chord = chord.parse::<Chord>()?;
+        let action = <BindingAction as clap::ValueEnum>::from_str(action, true)
+            .map_err(|_| format!("unknown action \"{action}\""))?;
+        Ok(Self {
+            modifiers: chord.modifiers,
+            key: chord.trigger,
+            action,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct KeyConfig {
+    // The chords and their actions. The modifier counts as held when all modifiers of any of
+    // them are held down, and the sequence ends when none of them are anymore.
+    pub bindings: Vec<Binding>,
+    // Held together with the modifier, flips the direction of the cycling actions
+    pub reverse_modifier: evdev_rs::enums::EV_KEY,
+    // Held together with the modifier, the workspace cycling actions take the focused
+    // container along
+    pub move_modifier: Option<evdev_rs::enums::EV_KEY>,
+    // Holding the trigger keeps advancing the selection with autorepeat events, at most once per
+    // this interval, None disables it
+    pub trigger_repeat_interval: Option<Duration>,
+    // Two taps of the modifier alone within this time switch to the previous workspace (like
+    // Trigger and EndMod), None disables it
+    pub double_tap_window: Option<Duration>,
+    // A modifier release followed by a re-press within this time is treated as a continuous
+    // hold, zero disables the grace window
+    pub modifier_release_grace: Duration,
+    // A trigger press within this time after the previous press of the same trigger is
+    // swallowed as a bounce, zero disables the debouncing
+    pub trigger_debounce: Duration,
+    // The trigger only counts if it's been pressed after all modifiers of its chord, e.g. its
+    // autorepeat doesn't cycle anymore once a modifier is released and pressed again
+    pub strict_chord_order: bool,
+    // Keys sending PinCurrent and RemoveCurrent when pressed during the sequence
+    pub pin_key: Option<evdev_rs::enums::EV_KEY>,
+    pub remove_key: Option<evdev_rs::enums::EV_KEY>,
+    // Sends Cancel when pressed during the sequence
+    pub cancel_key: evdev_rs::enums::EV_KEY,
+    // The number keys send JumpTo when pressed during the sequence
+    pub jump_keys: bool,
+    // Scrolling the mouse wheel with the modifier held cycles through the workspaces
+    pub scroll_wheel: bool,
+    // Keys recognized by their scancodes instead of the key codes
+    pub scancode_keys: Vec<ScancodeKey>,
+}
+
+impl KeyConfig {
+    pub fn modifier_keys(&self) -> impl Iterator<Item = evdev_rs::enums::EV_KEY> + '_ {
+        self.bindings
+            .iter()
+            .flat_map(|binding| binding.modifiers.iter().copied())
+    }
+
+    // The keys pressed with the modifiers, e.g. KEY_TAB
+    pub fn trigger_keys(&self) -> impl Iterator<Item = evdev_rs::enums::EV_KEY> + '_ {
+        self.bindings.iter().map(|binding| binding.key)
+    }
+
+    fn is_modifier(&self, key: evdev_rs::enums::EV_KEY) -> bool {
+        self.modifier_keys().any(|modifier| modifier == key)
+    }
+
+    fn is_trigger(&self, key: evdev_rs::enums::EV_KEY) -> bool {
+        self.trigger_keys().any(|trigger| trigger == key)
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.trigger_keys().any(|trigger| self.is_modifier(trigger)) {
+            return Err(
+                "the modifier keys overlap with the trigger key, check your key configuration"
+                    .into(),
+            );
+        }
+        for (i, binding) in self.bindings.iter().enumerate() {
+            let same_chord = |other: &&Binding| {
+                other.key == binding.key
+                    && other.modifiers.len() == binding.modifiers.len()
+                    && other
+                        .modifiers
+                        .iter()
+                        .all(|key| binding.modifiers.contains(key))
+            };
+            if self.bindings[i + 1..]
+                .iter()
+                .any(|other| same_chord(&other))
+            {
+                return Err(format!(
+                    "the chord {:?},{:?} is bound more than once",
+                    binding.modifiers, binding.key
+                )
+                .into());
+            }
+        }
+        if self.is_modifier(self.reverse_modifier) || self.is_trigger(self.reverse_modifier) {
+            return Err(
+                "the reverse modifier overlaps with the chord keys, check your key configuration"
+                    .into(),
+            );
+        }
+        if let Some(move_modifier) = self.move_modifier {
+            if self.is_modifier(move_modifier)
+                || self.is_trigger(move_modifier)
+                || move_modifier == self.reverse_modifier
+            {
+                return Err(
+                    "the move modifier overlaps with the chord keys or the reverse \
+                    modifier, check your key configuration"
+                        .into(),
+                );
+            }
+        }
+        let action_keys = [self.pin_key, self.remove_key, Some(self.cancel_key)];
+        for action_key in action_keys.into_iter().flatten() {
+            let in_chord = self.is_trigger(action_key)
+                || action_key == self.reverse_modifier
+                || Some(action_key) == self.move_modifier;
+            if in_chord || self.is_modifier(action_key) {
+                return Err(format!(
+                    "{action_key:?} is used both in the chord and as an action key"
+                )
+                .into());
+            }
+        }
+        let chord_keys = self
+            .modifier_keys()
+            .chain(self.trigger_keys())
+            .chain([self.reverse_modifier])
+            .chain(self.move_modifier)
+            .chain(action_keys.into_iter().flatten());
+        for key in chord_keys.filter(|_| self.jump_keys) {
+            if JUMP_KEYS.contains(&key) {
+                return Err(format!("{key:?} is used both as a jump key and in the chord").into());
+            }
+        }
+        for (i, key) in action_keys.iter().enumerate() {
+            if key.is_some() && action_keys[i + 1..].contains(key) {
+                return Err("the pin, remove and cancel keys must be different".into());
+            }
+        }
+        Ok(())
+    }
+}
+
+// Builds the key configuration anew, e.g. from the updated config file
+pub type KeyConfigLoader = Box<dyn Fn() -> Result<KeyConfig, Box<dyn Error>> + Send>;
+
 pub struct AltTabInterceptor {
-    in_device: Device,
-    out_device: UInputDevice,
+    source: Box<dyn EventSource>,
+    out_device: Option<Box<dyn EventSink>>,
+    output: OutputConfig,
     evt_tx: Sender<WorkspaceSwitcherEvent>,
     key_config: KeyConfig,
+    clock: Box<dyn Clock>,
     was_tab: bool,
+    // Modifier keys held down, and whether they make up one of the combinations
+    modifiers_held: Vec<evdev_rs::enums::EV_KEY>,
     meta_pressed: bool,
+    reverse_pressed: bool,
+    move_pressed: bool,
+    // The trigger whose press has been swallowed and its action, so its autorepeats and release
+    // must be swallowed too, even if the sequence has already ended (sway would get an orphan
+    // release otherwise)
+    trigger_swallowed: Option<(evdev_rs::enums::EV_KEY, BindingAction)>,
+    // Time of the last trigger press (or repeat) which advanced the selection
+    last_trigger: Option<Instant>,
+    // The last trigger press which ran its action, for the debouncing
+    last_trigger_press: Option<(evdev_rs::enums::EV_KEY, Instant)>,
+    // The modifier has been pressed with no other keys since, so its release is a tap
+    tap_candidate: bool,
+    // Time of the last tap, waiting for the second one
+    last_tap: Option<Instant>,
+    // Time of a modifier release which may still be cancelled by a re-press
+    pending_release: Option<Instant>,
+    // Keys held down in the order they've been pressed in, with the number of the press, only
+    // tracked with strict_chord_order
+    key_presses: Vec<(evdev_rs::enums::EV_KEY, u64)>,
+    press_count: u64,
+    // Forwarded events waiting for the SYN_REPORT closing their frame, the whole frame is
+    // written at once. Some virtual devices don't send it, so one is added if it doesn't come.
+    frame: Vec<InputEvent>,
+    // Action keys whose press has been swallowed, their autorepeats and releases are too
+    actions_swallowed: Vec<evdev_rs::enums::EV_KEY>,
+    // Keys pressed on the output device, they're released when pausing
+    keys_down: Vec<evdev_rs::enums::EV_KEY>,
+    // With GrabPolicy::Never, it's a dry run: there's no output device, and the swallowed key
+    // events are logged. There's no output device with GrabPolicy::Shared either.
+    grab: GrabPolicy,
+    // The events of the ungrabbed devices reach the rest of the system directly, so they
+    // aren't forwarded
+    grabbed: bool,
+    // Used on reload requests, the configuration can't be reloaded without it
+    key_config_loader: Option<KeyConfigLoader>,
+    // Creating a uinput device needs root, it isn't tried again after dropping the privileges
+    uinput_recreation: bool,
+    // The scancode of the frame being read, it comes before the key event
+    frame_scancode: Option<i32>,
+    // Keys pressed with one of the scancode_keys scancodes and the keys they stand for, their
+    // autorepeats and releases don't necessarily come with the scancode
+    scancode_keys_held: Vec<(evdev_rs::enums::EV_KEY, evdev_rs::enums::EV_KEY)>,
 }
 
 impl AltTabInterceptor {
     pub fn new(
-        in_device_path: &std::path::Path,
+        mut source: Box<dyn EventSource>,
         evt_tx: Sender<WorkspaceSwitcherEvent>,
         key_config: KeyConfig,
+        clock: Box<dyn Clock>,
+        output: OutputConfig,
+        grab: GrabPolicy,
     ) -> Result<Self, Box<dyn Error>> {
-        if key_config.trigger == key_config.modifier1
-            || Some(key_config.trigger) == key_config.modifier2
-        {
-            return Err(
-                "the modifier keys overlap with the trigger key, check your key configuration"
-                    .into(),
-            );
-        }
+        key_config.validate()?;
 
-        let file = std::fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(in_device_path)
-            .map_err(|e| {
-                format!(
-                    "can't open keyboard input device file ({}): {e}",
-                    in_device_path.display()
-                )
-            })?;
-
-        let mut in_device = Device::new_from_file(file)
-            .map_err(|e| format!("can't create libevdev input device: {e}"))?;
-        in_device
-            .grab(evdev_rs::GrabMode::Grab)
-            .map_err(|e| format!("can't grab the input device: {e}"))?;
-        let out_device = UInputDevice::create_from_device(&in_device)
-            .map_err(|e| format!("can't create a uinput device: {e}"))?;
+        let out_device = match grab {
+            GrabPolicy::Never | GrabPolicy::Shared => None,
+            _ => Self::create_output_device(source.as_mut(), &output)?,
+        };
 
         log::debug!("Initialized the keypress interceptor");
-        log::debug!("Keyboard input device: {}", in_device_path.display());
-        log::debug!(
-            "UInput device devnode: {}, syspath: {}",
-            out_device.devnode().unwrap_or("none"),
-            out_device.syspath().unwrap_or("none")
-        );
+        match &out_device {
+            Some(out_device) => log::debug!("Output device: {}", out_device.describe()),
+            None => log::debug!("No output device, non-intercepted events are dropped"),
+        }
 
         Ok(Self {
-            in_device,
+            source,
             out_device,
+            output,
             evt_tx,
             key_config,
+            clock,
             was_tab: false,
+            modifiers_held: Vec::new(),
             meta_pressed: false,
+            reverse_pressed: false,
+            move_pressed: false,
+            trigger_swallowed: None,
+            last_trigger: None,
+            last_trigger_press: None,
+            tap_candidate: false,
+            last_tap: None,
+            pending_release: None,
+            key_presses: Vec::new(),
+            press_count: 0,
+            frame: Vec::new(),
+            actions_swallowed: Vec::new(),
+            keys_down: Vec::new(),
+            grab,
+            grabbed: grab == GrabPolicy::Always,
+            key_config_loader: None,
+            uinput_recreation: true,
+            frame_scancode: None,
+            scancode_keys_held: Vec::new(),
         })
     }
 
+    pub fn set_key_config_loader(&mut self, loader: KeyConfigLoader) {
+        self.key_config_loader = Some(loader);
+    }
+
+    pub fn disable_uinput_recreation(&mut self) {
+        self.uinput_recreation = false;
+    }
+
     pub fn run(&mut self) {
         log::info!("Starting the keypress interceptor...");
 
         loop {
-            let ev = self.in_device.next_event(ReadFlag::BLOCKING);
-            match ev {
-                Ok((ReadStatus::Success, ev)) => {
-                    if let Some(ev) = self.on_event(ev) {
-                        self.out_device
-                            .write_event(&ev)
-                            .expect("error writing to the uinput device");
+            // If the frame isn't closed yet, check whether the device is going to close it
+            let timeout = if !self.frame.is_empty() {
+                Some(Duration::ZERO)
+            } else {
+                self.pending_release.map(|released_at| {
+                    self.key_config
+                        .modifier_release_grace
+                        .saturating_sub(self.clock.now().saturating_duration_since(released_at))
+                })
+            };
+
+            match self.source.next_event(timeout) {
+                Ok(Some(ev)) => {
+                    let (code, value) = (ev.event_code, ev.value);
+                    let matched = self.translate_scancode(&ev);
+                    match self.on_event(matched) {
+                        Some(_) if self.grabbed => self.forward(&ev),
+                        Some(_) => {}
+                        None if self.grab == GrabPolicy::Never && matches!(code, EV_KEY(_)) => {
+                            log::info!("Would swallow {code} {value}");
+                        }
+                        None => {}
                     }
                 }
-                Ok((ReadStatus::Sync, _)) => {
-                    log::warn!("There's no support for SYN_DROPPED yet, ignoring");
+                Ok(None) if !self.frame.is_empty() => {
+                    log::trace!("No SYN_REPORT after the forwarded events, adding one");
+                    let syn = EventCode::EV_SYN(EV_SYN::SYN_REPORT);
+                    self.forward(&InputEvent::new(&TimeVal::new(0, 0), &syn, 0));
+                }
+                Ok(None) => {
+                    log::debug!("Modifier release grace period expired, ending the sequence");
+                    self.pending_release = None;
+                    self.end_sequence();
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {
+                    self.handle_signal_request();
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    log::info!("The input source is closed, stopping the keypress interceptor");
+                    return;
+                }
+                Err(e) => {
+                    panic!("error reading from the input device: {e}");
+                }
+            }
+            self.update_lazy_grab();
+        }
+    }
+
+    // With the lazy grab, the devices are grabbed while the modifier is held or the sequence
+    // lasts, which is all the time the trigger needs to be swallowed
+    fn update_lazy_grab(&mut self) {
+        let grab = self.meta_pressed || self.was_tab || self.pending_release.is_some();
+        if self.grab != GrabPolicy::Lazy || grab == self.grabbed {
+            return;
+        }
+        if grab {
+            log::debug!("Grabbing the input devices");
+        } else {
+            log::debug!("Ungrabbing the input devices");
+            // The keys pressed through the output device would be released on the real one
+            self.release_keys_down();
+        }
+        self.grabbed = grab;
+        if let Err(e) = self.source.set_grabbed(grab) {
+            log::error!("Can't switch the input devices' grab: {e}");
+        }
+    }
+
+    fn create_output_device(
+        source: &mut dyn EventSource,
+        output: &OutputConfig,
+    ) -> Result<Option<Box<dyn EventSink>>, Box<dyn Error>> {
+        match output.backend {
+            OutputBackend::Uinput => Ok(source
+                .create_output_device(&output.uinput)
+                .map_err(|e| format!("can't create a uinput device: {e}"))?),
+            OutputBackend::Wayland => {
+                Ok(Some(Box::new(VirtualKeyboard::connect().map_err(|e| {
+                    format!("can't create a virtual keyboard: {e}")
+                })?)))
+            }
+        }
+    }
+
+    fn handle_signal_request(&mut self) {
+        while let Some(request) = signals::take_request() {
+            match request {
+                Request::Pause => log::info!("Pausing the interception, the keyboard is released"),
+                Request::Resume => log::info!("Resuming the interception"),
+                Request::Suspend => log::debug!("Closing the input devices for the sleep"),
+                Request::Wake => log::debug!("Reopening the input devices after the sleep"),
+                Request::Reload => {
+                    self.reload_key_config();
+                    continue;
                 }
-                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    log::warn!("next_event() should block, something is wrong");
+            }
+            if matches!(request, Request::Pause | Request::Suspend) {
+                if self.was_tab {
+                    self.pending_release = None;
+                    self.end_sequence();
                 }
-                Err(_) => {
-                    ev.expect("error reading from the input device");
+                self.release_keys_down();
+            }
+            match request {
+                Request::Pause | Request::Resume => {
+                    if let Err(e) = self.source.set_paused(request == Request::Pause) {
+                        log::error!("Can't switch the input devices' grab: {e}");
+                    }
+                }
+                Request::Suspend | Request::Wake => {
+                    self.source.set_suspended(request == Request::Suspend)
                 }
+                Request::Reload => {}
+            }
+        }
+    }
+
+    // The grab and the output device stay, only the way the keys are handled changes
+    fn reload_key_config(&mut self) {
+        let Some(loader) = &self.key_config_loader else {
+            log::warn!("There's no key config file to reload");
+            return;
+        };
+        let key_config = match loader().and_then(|config| config.validate().map(|_| config)) {
+            Ok(key_config) => key_config,
+            Err(e) => {
+                log::error!("Can't reload the key configuration, keeping the old one: {e}");
+                return;
+            }
+        };
+        if self.was_tab {
+            self.pending_release = None;
+            self.end_sequence();
+        }
+        self.key_config = key_config;
+        // The held keys may have a different meaning now
+        self.resync_key_state();
+        log::info!("The key configuration is reloaded");
+    }
+
+    // The keys are going to be released on the real keyboard, which isn't seen by us anymore
+    fn release_keys_down(&mut self) {
+        for key in std::mem::take(&mut self.keys_down) {
+            self.forward(&InputEvent::new(&TimeVal::new(0, 0), &EV_KEY(key), 0));
+        }
+        let syn = EventCode::EV_SYN(EV_SYN::SYN_REPORT);
+        self.forward(&InputEvent::new(&TimeVal::new(0, 0), &syn, 0));
+    }
+
+    // Adds the event to the frame, and sends the frame to the output device (if there's one)
+    // when it's complete
+    fn forward(&mut self, ev: &InputEvent) {
+        if let EV_KEY(key) = ev.event_code {
+            self.keys_down.retain(|&down| down != key);
+            if ev.value != 0 {
+                self.keys_down.push(key);
             }
         }
+        self.frame.push(ev.clone());
+        if ev.event_code != EventCode::EV_SYN(EV_SYN::SYN_REPORT) {
+            return;
+        }
+
+        let frame = std::mem::take(&mut self.frame);
+        // Only scan codes are left from the frames of swallowed keys, they mean nothing alone
+        let is_empty = frame.iter().all(|ev| {
+            matches!(
+                ev.event_code,
+                EventCode::EV_SYN(_) | EventCode::EV_MSC(EV_MSC::MSC_SCAN)
+            )
+        });
+        if is_empty {
+            return;
+        }
+        let Some(out_device) = &mut self.out_device else {
+            log::trace!("Dropping events: {:?}", frame);
+            return;
+        };
+        let Err(e) = out_device.write_frame(&frame) else {
+            return;
+        };
+
+        if self.output.backend == OutputBackend::Uinput && !self.uinput_recreation {
+            log::error!("Can't write to the output device, dropping the events: {e}");
+            return;
+        }
+        // Don't leave the user without a keyboard because of a single failed write (e.g. the
+        // device has been removed), create a new device and try once more
+        log::error!("Can't write to the output device, recreating it: {e}");
+        match Self::create_output_device(self.source.as_mut(), &self.output) {
+            Ok(out_device) => self.out_device = out_device,
+            Err(e) => {
+                log::error!("Can't recreate the output device: {e}");
+                return;
+            }
+        }
+        if let Some(Err(e)) = self.out_device.as_mut().map(|dev| dev.write_frame(&frame)) {
+            log::error!("Can't write to the recreated output device, dropping the events: {e}");
+        }
+    }
+
+    fn end_sequence(&mut self) {
+        self.evt_tx
+            .send(WorkspaceSwitcherEvent::EndMod)
+            .expect("can't send a key event, channel is dead");
+        self.was_tab = false;
     }
 
     // This function is called on each event got from a configured input device.
     // The return value is an optional event to send to the fake uinput device.
     fn on_event(&mut self, evt: InputEvent) -> Option<InputEvent> {
         // evt.value in EV_KEY is 0 for release, 1 for press and 2 for hold.
+        if let EV_KEY(key) = evt.event_code {
+            self.track_key_press(key, evt.value);
+        }
+        if let (EV_KEY(key), 1) = (evt.event_code, evt.value) {
+            if !self.key_config.is_modifier(key) {
+                self.tap_candidate = false;
+                self.last_tap = None;
+            }
+        }
+
         match (evt.event_code, evt.value) {
-            (EV_KEY(mod_), 0 | 1)
-                if mod_ == self.key_config.modifier1 || Some(mod_) == self.key_config.modifier2 =>
+            (EventCode::EV_SYN(EV_SYN::SYN_DROPPED), _) => {
+                self.resync_key_state();
+                None
+            }
+            // Each notch down selects the next workspace, up - the previous one. The
+            // high-resolution events of the same scrolling are swallowed too.
+            (EventCode::EV_REL(EV_REL::REL_WHEEL | EV_REL::REL_WHEEL_HI_RES), notches)
+                if self.key_config.scroll_wheel && self.meta_pressed =>
             {
-                self.meta_pressed = evt.value == 1;
-                if evt.value == 0 && self.was_tab {
-                    self.evt_tx
-                        .send(WorkspaceSwitcherEvent::EndMod)
-                        .expect("can't send a key event, channel is dead");
-                    self.was_tab = false;
+                if evt.event_code == EventCode::EV_REL(EV_REL::REL_WHEEL) {
+                    self.tap_candidate = false;
+                    self.last_tap = None;
+                    let action = match notches > 0 {
+                        true => BindingAction::CycleBack,
+                        false => BindingAction::CycleForward,
+                    };
+                    for _ in 0..notches.unsigned_abs() {
+                        self.run_action(action);
+                    }
+                }
+                None
+            }
+            (EV_KEY(mod_), 0 | 1) if self.key_config.is_modifier(mod_) => {
+                self.modifiers_held.retain(|&held| held != mod_);
+                if evt.value == 1 {
+                    self.modifiers_held.push(mod_);
+                }
+                let was_pressed = self.meta_pressed;
+                self.update_meta_pressed();
+
+                if self.meta_pressed && !was_pressed && self.pending_release.take().is_some() {
+                    log::debug!(
+                        "Modifier re-pressed within the grace period, continuing the sequence"
+                    );
+                }
+                if self.meta_pressed && !was_pressed {
+                    self.tap_candidate = !self.was_tab;
+                }
+                if !self.meta_pressed && was_pressed && self.was_tab {
+                    if self.key_config.modifier_release_grace.is_zero() {
+                        self.end_sequence();
+                    } else {
+                        self.pending_release = Some(self.clock.now());
+                    }
+                } else if !self.meta_pressed && was_pressed && self.tap_candidate {
+                    self.on_tap();
+                }
+                Some(evt)
+            }
+            (EV_KEY(trig), 1) if self.key_config.is_trigger(trig) => {
+                let in_order = self.chord_in_order(trig);
+                let action = self.bound_action(trig);
+                if action.is_some() && !in_order {
+                    log::debug!("Not triggering, {trig:?} has been pressed before the modifiers");
+                }
+                self.trigger_swallowed = action.filter(|_| in_order).map(|a| (trig, a));
+                match self.trigger_swallowed {
+                    Some(_) if self.is_bounce(trig) => {
+                        log::debug!("Ignoring a bounce of {trig:?}");
+                        None
+                    }
+                    Some((_, action)) => {
+                        self.last_trigger_press = Some((trig, self.clock.now()));
+                        self.run_action(action);
+                        None
+                    }
+                    None => Some(evt),
+                }
+            }
+            (EV_KEY(trig), 2) if self.trigger_swallowed.is_some_and(|(key, _)| key == trig) => {
+                let repeat_due = match (self.key_config.trigger_repeat_interval, self.last_trigger)
+                {
+                    (Some(interval), Some(last)) => {
+                        self.clock.now().saturating_duration_since(last) >= interval
+                    }
+                    _ => false,
+                };
+                if let Some((_, action)) = self.trigger_swallowed {
+                    let active = self.was_tab && self.meta_pressed && self.chord_in_order(trig);
+                    if repeat_due && active && action.repeats() {
+                        self.run_action(action);
+                    }
                 }
+                None
+            }
+            // Not a part of the chord, so it's passed through, but it isn't an "other key"
+            (EV_KEY(key), 0 | 1) if key == self.key_config.reverse_modifier => {
+                self.reverse_pressed = evt.value == 1;
                 Some(evt)
             }
-            (EV_KEY(trig), 1) if trig == self.key_config.trigger => {
-                if self.meta_pressed {
-                    self.was_tab = true;
+            (EV_KEY(key), 0 | 1) if Some(key) == self.key_config.move_modifier => {
+                self.move_pressed = evt.value == 1;
+                Some(evt)
+            }
+            (EV_KEY(trig), 0) if self.trigger_swallowed.is_some_and(|(key, _)| key == trig) => {
+                self.trigger_swallowed = None;
+                None
+            }
+            (EV_KEY(key), 1) if self.was_tab && self.meta_pressed => {
+                if let Some(action) = self.action_event(key) {
+                    self.actions_swallowed.push(key);
+                    if let WorkspaceSwitcherEvent::Cancel | WorkspaceSwitcherEvent::JumpTo(_) =
+                        action
+                    {
+                        // The modifier release won't commit anything then
+                        self.was_tab = false;
+                        self.pending_release = None;
+                    }
                     self.evt_tx
-                        .send(WorkspaceSwitcherEvent::Trigger)
+                        .send(action)
                         .expect("can't send a key event, channel is dead");
                     None
                 } else {
                     Some(evt)
                 }
             }
+            (EV_KEY(key), 0 | 2) if self.actions_swallowed.contains(&key) => {
+                if evt.value == 0 {
+                    self.actions_swallowed.retain(|&swallowed| swallowed != key);
+                }
+                None
+            }
             _ => Some(evt),
         }
     }
+
+    // Some events have been dropped, so the key state tracked from them can't be trusted, take
+    // it from the source instead. The events the source sends to catch up (e.g. the releases
+    // that were missed) are handled as usual afterwards.
+    fn resync_key_state(&mut self) {
+        self.scancode_keys_held
+            .retain(|&(key, _)| self.source.key_state(key) != Some(false));
+        let mut modifiers_held = Vec::new();
+        for key in self.key_config.modifier_keys() {
+            let by_scancode = self.scancode_keys_held.iter().any(|&(_, held)| held == key);
+            match self.source.key_state(key) {
+                _ if by_scancode => modifiers_held.push(key),
+                Some(true) => modifiers_held.push(key),
+                Some(false) => {}
+                None => return,
+            }
+        }
+        self.modifiers_held = modifiers_held;
+        self.update_meta_pressed();
+        self.reverse_pressed =
+            self.source.key_state(self.key_config.reverse_modifier) == Some(true);
+        self.move_pressed = self
+            .key_config
+            .move_modifier
+            .is_some_and(|key| self.source.key_state(key) == Some(true));
+        // The modifiers held down without their presses seen don't count with
+        // strict_chord_order, it's unknown whether they've been pressed before the trigger
+        self.key_presses
+            .retain(|&(key, _)| self.source.key_state(key) == Some(true));
+
+        if self.was_tab && !self.meta_pressed {
+            log::debug!("The modifier has been released while events were dropped");
+            self.pending_release = None;
+            self.end_sequence();
+        }
+    }
+
+    fn on_tap(&mut self) {
+        self.tap_candidate = false;
+        let Some(window) = self.key_config.double_tap_window else {
+            return;
+        };
+        let now = self.clock.now();
+        match self.last_tap.take() {
+            Some(last_tap) if now.saturating_duration_since(last_tap) <= window => {
+                log::debug!("Modifier double-tapped, switching to the previous workspace");
+                self.evt_tx
+                    .send(WorkspaceSwitcherEvent::Trigger)
+                    .expect("can't send a key event, channel is dead");
+                self.end_sequence();
+            }
+            _ => self.last_tap = Some(now),
+        }
+    }
+
+    // The action of the binding for the trigger whose modifiers are all held down, the first
+    // one if there are several
+    fn bound_action(&self, trigger: evdev_rs::enums::EV_KEY) -> Option<BindingAction> {
+        self.key_config
+            .bindings
+            .iter()
+            .find(|binding| {
+                binding.key == trigger
+                    && binding
+                        .modifiers
+                        .iter()
+                        .all(|key| self.modifiers_held.contains(key))
+            })
+            .map(|binding| binding.action)
+    }
+
+    fn run_action(&mut self, action: BindingAction) {
+        let evt = match (action, self.reverse_pressed) {
+            (BindingAction::CycleForward, false) | (BindingAction::CycleBack, true) => {
+                match self.move_pressed {
+                    true => WorkspaceSwitcherEvent::TriggerMove,
+                    false => WorkspaceSwitcherEvent::Trigger,
+                }
+            }
+            (BindingAction::CycleForward, true) | (BindingAction::CycleBack, false) => {
+                match self.move_pressed {
+                    true => WorkspaceSwitcherEvent::TriggerMoveBack,
+                    false => WorkspaceSwitcherEvent::TriggerBack,
+                }
+            }
+            (BindingAction::WindowForward, false) | (BindingAction::WindowBack, true) => {
+                WorkspaceSwitcherEvent::WindowTrigger
+            }
+            (BindingAction::WindowForward, true) | (BindingAction::WindowBack, false) => {
+                WorkspaceSwitcherEvent::WindowTriggerBack
+            }
+            (BindingAction::JumpToLast, _) => {
+                // Commits whatever has been selected first, then acts like a complete sequence
+                if self.was_tab {
+                    self.pending_release = None;
+                    self.end_sequence();
+                }
+                self.send(WorkspaceSwitcherEvent::Trigger);
+                self.end_sequence();
+                return;
+            }
+            (BindingAction::Cancel, _) => {
+                // The modifier release won't commit anything then
+                self.was_tab = false;
+                self.pending_release = None;
+                self.send(WorkspaceSwitcherEvent::Cancel);
+                return;
+            }
+        };
+        self.was_tab = true;
+        self.last_trigger = Some(self.clock.now());
+        self.send(evt);
+    }
+
+    fn is_bounce(&self, trigger: evdev_rs::enums::EV_KEY) -> bool {
+        let debounce = self.key_config.trigger_debounce;
+        self.last_trigger_press.is_some_and(|(key, pressed_at)| {
+            key == trigger && self.clock.now().saturating_duration_since(pressed_at) < debounce
+        })
+    }
+
+    fn send(&self, evt: WorkspaceSwitcherEvent) {
+        self.evt_tx
+            .send(evt)
+            .expect("can't send a key event, channel is dead");
+    }
+
+    fn update_meta_pressed(&mut self) {
+        self.meta_pressed = self.key_config.bindings.iter().any(|binding| {
+            binding
+                .modifiers
+                .iter()
+                .all(|key| self.modifiers_held.contains(key))
+        });
+    }
+
+    fn action_event(&self, key: evdev_rs::enums::EV_KEY) -> Option<WorkspaceSwitcherEvent> {
+        if Some(key) == self.key_config.pin_key {
+            Some(WorkspaceSwitcherEvent::PinCurrent)
+        } else if Some(key) == self.key_config.remove_key {
+            Some(WorkspaceSwitcherEvent::RemoveCurrent)
+        } else if key == self.key_config.cancel_key {
+            Some(WorkspaceSwitcherEvent::Cancel)
+        } else if self.key_config.jump_keys {
+            let idx = JUMP_KEYS.iter().position(|&jump_key| jump_key == key)?;
+            Some(WorkspaceSwitcherEvent::JumpTo(idx + 1))
+        } else {
+            None
+        }
+    }
+
+    // The event as seen by the chord detection: a key pressed with one of the configured
+    // scancodes becomes the key the scancode stands for, until it's released
+    fn translate_scancode(&mut self, ev: &InputEvent) -> InputEvent {
+        match ev.event_code {
+            EventCode::EV_MSC(EV_MSC::MSC_SCAN) => self.frame_scancode = Some(ev.value),
+            EventCode::EV_SYN(EV_SYN::SYN_REPORT) => self.frame_scancode = None,
+            EV_KEY(key) => {
+                if ev.value == 1 {
+                    let scancode_key =
+                        self.key_config.scancode_keys.iter().find(|scancode_key| {
+                            Some(scancode_key.scancode) == self.frame_scancode
+                        });
+                    self.scancode_keys_held.retain(|&(held, _)| held != key);
+                    if let Some(scancode_key) = scancode_key {
+                        self.scancode_keys_held.push((key, scancode_key.key));
+                    }
+                }
+                let translated = self
+                    .scancode_keys_held
+                    .iter()
+                    .find(|&&(held, _)| held == key)
+                    .map(|&(_, translated)| translated);
+                if ev.value == 0 {
+                    self.scancode_keys_held.retain(|&(held, _)| held != key);
+                }
+                if let Some(translated) = translated {
+                    return InputEvent::new(&ev.time, &EV_KEY(translated), ev.value);
+                }
+            }
+            _ => {}
+        }
+        ev.clone()
+    }
+
+    // A press of a key which is already held down (e.g. on another keyboard) keeps the number
+    // of the first one
+    fn track_key_press(&mut self, key: evdev_rs::enums::EV_KEY, value: i32) {
+        if !self.key_config.strict_chord_order {
+            return;
+        }
+        match value {
+            0 => self.key_presses.retain(|&(held, _)| held != key),
+            1 if !self.key_presses.iter().any(|&(held, _)| held == key) => {
+                self.key_presses.push((key, self.press_count));
+                self.press_count += 1;
+            }
+            _ => {}
+        }
+    }
+
+    // Whether the trigger has been pressed after all modifiers of its binding, always true
+    // without strict_chord_order
+    fn chord_in_order(&self, trigger: evdev_rs::enums::EV_KEY) -> bool {
+        if !self.key_config.strict_chord_order {
+            return true;
+        }
+        let pressed = |key| {
+            self.key_presses
+                .iter()
+                .find(|&&(held, _)| held == key)
+                .map(|&(_, press)| press)
+        };
+        let Some(trigger_press) = pressed(trigger) else {
+            return false;
+        };
+        self.key_config.bindings.iter().any(|binding| {
+            binding.key == trigger
+                && binding
+                    .modifiers
+                    .iter()
+                    .all(|&key| pressed(key).is_some_and(|press| press < trigger_press))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    use evdev_rs::enums::EV_KEY::*;
+
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::output::UinputProperties;
+
+    #[derive(Clone)]
+    enum Step {
+        Event(InputEvent),
+        // Time passing with no events
+        Wait(Duration),
+    }
+
+    // The frames written to the output devices, with the number of the device
+    type Written = Arc<Mutex<Vec<(usize, Vec<String>)>>>;
+
+    // Records the frames written to it, the first failing_writes of them fail
+    struct FakeSink {
+        device: usize,
+        failing_writes: usize,
+        written: Written,
+    }
+
+    impl EventSink for FakeSink {
+        fn write_event(&mut self, ev: &InputEvent) -> std::io::Result<()> {
+            self.write_frame(std::slice::from_ref(ev))
+        }
+
+        fn write_frame(&mut self, events: &[InputEvent]) -> std::io::Result<()> {
+            if self.failing_writes > 0 {
+                self.failing_writes -= 1;
+                return Err(std::io::Error::other("the device is gone"));
+            }
+            let frame = events
+                .iter()
+                .map(|ev| format!("{} {}", ev.event_code, ev.value))
+                .collect();
+            self.written.lock().unwrap().push((self.device, frame));
+            Ok(())
+        }
+
+        fn describe(&self) -> String {
+            format!("fake device {}", self.device)
+        }
+    }
+
+    // Plays the steps back, advancing the clock through the waits, and ends with an EOF. The
+    // writes to the first output device fail failing_writes times.
+    #[derive(Default)]
+    struct ScriptedSource {
+        steps: VecDeque<Step>,
+        clock: ManualClock,
+        devices_created: usize,
+        failing_writes: usize,
+        written: Written,
+    }
+
+    impl EventSource for ScriptedSource {
+        fn next_event(&mut self, timeout: Option<Duration>) -> std::io::Result<Option<InputEvent>> {
+            let mut timeout = timeout;
+            while let Some(step) = self.steps.pop_front() {
+                match (step, timeout) {
+                    (Step::Event(ev), _) => return Ok(Some(ev)),
+                    (Step::Wait(wait), Some(left)) if left <= wait => {
+                        self.clock.advance(left);
+                        self.steps.push_front(Step::Wait(wait - left));
+                        return Ok(None);
+                    }
+                    (Step::Wait(wait), _) => {
+                        self.clock.advance(wait);
+                        timeout = timeout.map(|left| left - wait);
+                    }
+                }
+            }
+            Err(std::io::ErrorKind::UnexpectedEof.into())
+        }
+
+        fn create_output_device(
+            &mut self,
+            _properties: &UinputProperties,
+        ) -> std::io::Result<Option<Box<dyn EventSink>>> {
+            let sink = FakeSink {
+                device: self.devices_created,
+                failing_writes: std::mem::take(&mut self.failing_writes),
+                written: self.written.clone(),
+            };
+            self.devices_created += 1;
+            Ok(Some(Box::new(sink)))
+        }
+
+        fn key_state(&self, _key: evdev_rs::enums::EV_KEY) -> Option<bool> {
+            None
+        }
+
+        fn set_paused(&mut self, _paused: bool) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn set_grabbed(&mut self, _grabbed: bool) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn set_suspended(&mut self, _suspended: bool) {}
+    }
+
+    fn syn() -> Step {
+        Step::Event(InputEvent::new(
+            &TimeVal::new(0, 0),
+            &EventCode::EV_SYN(EV_SYN::SYN_REPORT),
+            0,
+        ))
+    }
+
+    // A key event alone, without the SYN_REPORT
+    fn raw_key(key: evdev_rs::enums::EV_KEY, value: i32) -> Step {
+        Step::Event(InputEvent::new(&TimeVal::new(0, 0), &EV_KEY(key), value))
+    }
+
+    // A key event in its own frame
+    fn key(key: evdev_rs::enums::EV_KEY, value: i32) -> Vec<Step> {
+        vec![raw_key(key, value), syn()]
+    }
+
+    fn wait(ms: u64) -> Vec<Step> {
+        vec![Step::Wait(Duration::from_millis(ms))]
+    }
+
+    // Meta+Tab cycles forward
+    fn key_config() -> KeyConfig {
+        KeyConfig {
+            bindings: vec![Binding {
+                modifiers: vec![KEY_LEFTMETA],
+                key: KEY_TAB,
+                action: BindingAction::CycleForward,
+            }],
+            reverse_modifier: KEY_LEFTSHIFT,
+            move_modifier: None,
+            trigger_repeat_interval: None,
+            double_tap_window: None,
+            modifier_release_grace: Duration::ZERO,
+            trigger_debounce: Duration::ZERO,
+            strict_chord_order: false,
+            pin_key: None,
+            remove_key: None,
+            cancel_key: KEY_ESC,
+            jump_keys: false,
+            scroll_wheel: false,
+            scancode_keys: Vec::new(),
+        }
+    }
+
+    fn source(steps: Vec<Vec<Step>>) -> ScriptedSource {
+        ScriptedSource {
+            steps: steps.concat().into(),
+            ..ScriptedSource::default()
+        }
+    }
+
+    fn run(key_config: KeyConfig, steps: Vec<Vec<Step>>) -> Vec<String> {
+        run_source(key_config, source(steps))
+    }
+
+    // Runs the interceptor until the steps run out, returns the events sent to the switcher
+    fn run_source(key_config: KeyConfig, source: ScriptedSource) -> Vec<String> {
+        let clock = source.clock.clone();
+        let (evt_tx, evt_rx) = std::sync::mpsc::channel();
+        let output = OutputConfig {
+            backend: OutputBackend::Uinput,
+            uinput: UinputProperties::default(),
+        };
+        let mut interceptor = AltTabInterceptor::new(
+            Box::new(source),
+            evt_tx,
+            key_config,
+            Box::new(clock),
+            output,
+            GrabPolicy::Always,
+        )
+        .unwrap();
+        interceptor.run();
+        drop(interceptor);
+        evt_rx.iter().map(|evt| format!("{evt:?}")).collect()
+    }
+
+    #[test]
+    fn sequence_ends_when_the_grace_period_expires() {
+        let key_config = KeyConfig {
+            modifier_release_grace: Duration::from_millis(100),
+            ..key_config()
+        };
+        let steps = vec![
+            key(KEY_LEFTMETA, 1),
+            key(KEY_TAB, 1),
+            key(KEY_TAB, 0),
+            key(KEY_LEFTMETA, 0),
+            wait(99),
+        ];
+        assert_eq!(run(key_config.clone(), steps.clone()), ["Trigger"]);
+        let steps = [steps, vec![wait(1)]].concat();
+        assert_eq!(run(key_config, steps), ["Trigger", "EndMod"]);
+    }
+
+    #[test]
+    fn trigger_bounces_are_ignored() {
+        let key_config = KeyConfig {
+            trigger_debounce: Duration::from_millis(30),
+            ..key_config()
+        };
+        let tab = || [key(KEY_TAB, 1), key(KEY_TAB, 0)].concat();
+        let steps = vec![
+            key(KEY_LEFTMETA, 1),
+            tab(),
+            wait(29),
+            tab(),
+            wait(1),
+            tab(),
+            key(KEY_LEFTMETA, 0),
+        ];
+        assert_eq!(run(key_config, steps), ["Trigger", "Trigger", "EndMod"]);
+    }
+
+    #[test]
+    fn modifier_double_tap_switches_to_the_previous_workspace() {
+        let key_config = KeyConfig {
+            double_tap_window: Some(Duration::from_millis(300)),
+            ..key_config()
+        };
+        let tap = || [key(KEY_LEFTMETA, 1), key(KEY_LEFTMETA, 0)].concat();
+        let steps = vec![tap(), wait(300), tap()];
+        assert_eq!(run(key_config.clone(), steps), ["Trigger", "EndMod"]);
+        let steps = vec![tap(), wait(301), tap()];
+        assert_eq!(run(key_config.clone(), steps), Vec::<String>::new());
+        // A key pressed in between makes them not taps
+        let steps = vec![tap(), key(KEY_A, 1), key(KEY_A, 0), tap()];
+        assert_eq!(run(key_config, steps), Vec::<String>::new());
+    }
+
+    #[test]
+    fn trigger_repeats_at_most_once_per_interval() {
+        let key_config = KeyConfig {
+            trigger_repeat_interval: Some(Duration::from_millis(100)),
+            ..key_config()
+        };
+        let repeat = || [wait(40), key(KEY_TAB, 2)].concat();
+        let steps = vec![
+            key(KEY_LEFTMETA, 1),
+            key(KEY_TAB, 1),
+            // At 40, 80, 120 (runs), 160 and 200
+            repeat(),
+            repeat(),
+            repeat(),
+            repeat(),
+            repeat(),
+            key(KEY_TAB, 0),
+            key(KEY_LEFTMETA, 0),
+        ];
+        assert_eq!(run(key_config, steps), ["Trigger", "Trigger", "EndMod"]);
+    }
+
+    #[test]
+    fn frame_is_written_whole_to_the_recreated_device() {
+        let scan = EventCode::EV_MSC(EV_MSC::MSC_SCAN);
+        let steps = vec![
+            vec![Step::Event(InputEvent::new(
+                &TimeVal::new(0, 0),
+                &scan,
+                0x70004,
+            ))],
+            key(KEY_A, 1),
+            key(KEY_A, 0),
+        ];
+        let source = ScriptedSource {
+            failing_writes: 1,
+            ..source(steps)
+        };
+        let written = source.written.clone();
+        assert_eq!(run_source(key_config(), source), Vec::<String>::new());
+        let frame = |events: &[&str]| events.iter().map(|ev| ev.to_string()).collect();
+        assert_eq!(
+            *written.lock().unwrap(),
+            [
+                (1, frame(&["MSC_SCAN 458756", "KEY_A 1", "SYN_REPORT 0"])),
+                (1, frame(&["KEY_A 0", "SYN_REPORT 0"])),
+            ]
+        );
+    }
+
+    #[test]
+    fn uinput_device_is_not_recreated_when_disabled() {
+        let source = ScriptedSource {
+            failing_writes: 1,
+            ..source(vec![key(KEY_A, 1), key(KEY_A, 0)])
+        };
+        let written = source.written.clone();
+        let (evt_tx, _evt_rx) = std::sync::mpsc::channel();
+        let output = OutputConfig {
+            backend: OutputBackend::Uinput,
+            uinput: UinputProperties::default(),
+        };
+        let clock = Box::new(source.clock.clone());
+        let mut interceptor = AltTabInterceptor::new(
+            Box::new(source),
+            evt_tx,
+            key_config(),
+            clock,
+            output,
+            GrabPolicy::Always,
+        )
+        .unwrap();
+        interceptor.disable_uinput_recreation();
+        interceptor.run();
+
+        let frame = |events: &[&str]| events.iter().map(|ev| ev.to_string()).collect();
+        assert_eq!(
+            *written.lock().unwrap(),
+            [(0, frame(&["KEY_A 0", "SYN_REPORT 0"]))]
+        );
+    }
+
+    #[test]
+    fn modifier_repress_within_the_grace_period_continues_the_sequence() {
+        let key_config = KeyConfig {
+            modifier_release_grace: Duration::from_millis(100),
+            ..key_config()
+        };
+        let steps = vec![
+            key(KEY_LEFTMETA, 1),
+            key(KEY_TAB, 1),
+            key(KEY_TAB, 0),
+            // A spurious release, e.g. a flaky switch
+            key(KEY_LEFTMETA, 0),
+            wait(50),
+            key(KEY_LEFTMETA, 1),
+            // Well past the grace period, with the modifier held
+            wait(500),
+        ];
+        assert_eq!(run(key_config, steps), ["Trigger"]);
+    }
+
+    // The trigger's press, autorepeat and release are all swallowed, whichever key is released
+    // first
+    fn check_release_order(steps: Vec<Vec<Step>>) {
+        let source = source(steps);
+        let written = source.written.clone();
+        assert_eq!(run_source(key_config(), source), ["Trigger", "EndMod"]);
+        let written = written.lock().unwrap();
+        let frames = written.iter().map(|(_, frame)| frame.join(", "));
+        assert_eq!(
+            frames.collect::<Vec<_>>(),
+            [
+                "KEY_LEFTMETA 1, SYN_REPORT 0",
+                "KEY_LEFTMETA 0, SYN_REPORT 0"
+            ]
+        );
+    }
+
+    #[test]
+    fn modifier_released_before_the_trigger() {
+        check_release_order(vec![
+            key(KEY_LEFTMETA, 1),
+            key(KEY_TAB, 1),
+            key(KEY_TAB, 2),
+            key(KEY_LEFTMETA, 0),
+            key(KEY_TAB, 2),
+            key(KEY_TAB, 0),
+        ]);
+    }
+
+    #[test]
+    fn trigger_released_before_the_modifier() {
+        check_release_order(vec![
+            key(KEY_LEFTMETA, 1),
+            key(KEY_TAB, 1),
+            key(KEY_TAB, 2),
+            key(KEY_TAB, 0),
+            key(KEY_LEFTMETA, 0),
+        ]);
+    }
+
+    #[test]
+    fn modifier_pressed_after_the_trigger_stops_the_repeat_with_strict_order() {
+        let key_config = |strict_chord_order| KeyConfig {
+            trigger_repeat_interval: Some(Duration::from_millis(100)),
+            modifier_release_grace: Duration::from_millis(100),
+            strict_chord_order,
+            ..key_config()
+        };
+        let repeat = || [wait(100), key(KEY_TAB, 2)].concat();
+        let steps = vec![
+            key(KEY_LEFTMETA, 1),
+            key(KEY_TAB, 1),
+            repeat(),
+            // The modifier goes down again after the trigger, within the grace period
+            key(KEY_LEFTMETA, 0),
+            key(KEY_LEFTMETA, 1),
+            repeat(),
+            repeat(),
+        ];
+        let trigger = "Trigger";
+        assert_eq!(run(key_config(false), steps.clone()), [trigger; 4]);
+        assert_eq!(run(key_config(true), steps), [trigger; 2]);
+    }
+
+    #[test]
+    fn trigger_held_before_the_modifier_doesnt_count_with_strict_order() {
+        let key_config = |strict_chord_order| KeyConfig {
+            strict_chord_order,
+            ..key_config()
+        };
+        let steps = vec![
+            key(KEY_TAB, 1),
+            key(KEY_LEFTMETA, 1),
+            // The same key pressed on another keyboard, with the first one still down
+            key(KEY_TAB, 1),
+            key(KEY_TAB, 0),
+            key(KEY_LEFTMETA, 0),
+        ];
+        assert_eq!(run(key_config(false), steps.clone()), ["Trigger", "EndMod"]);
+        assert_eq!(run(key_config(true), steps), Vec::<String>::new());
+    }
+
+    #[test]
+    fn other_keys_held_dont_matter_with_strict_order() {
+        let key_config = KeyConfig {
+            strict_chord_order: true,
+            ..key_config()
+        };
+        let steps = vec![
+            key(KEY_A, 1),
+            key(KEY_LEFTMETA, 1),
+            key(KEY_LEFTCTRL, 1),
+            key(KEY_TAB, 1),
+            key(KEY_TAB, 0),
+            key(KEY_LEFTMETA, 0),
+        ];
+        assert_eq!(run(key_config, steps), ["Trigger", "EndMod"]);
+    }
+
+    #[test]
+    fn unframed_events_are_framed_on_output() {
+        let steps = vec![
+            vec![raw_key(KEY_A, 1)],
+            wait(5),
+            vec![raw_key(KEY_A, 0)],
+            wait(5),
+            // A device sending the SYN_REPORT gets no extra one
+            key(KEY_B, 1),
+            vec![raw_key(KEY_B, 0)],
+            wait(5),
+        ];
+        let source = source(steps);
+        let written = source.written.clone();
+        assert_eq!(run_source(key_config(), source), Vec::<String>::new());
+        let written = written.lock().unwrap();
+        let frames = written.iter().map(|(_, frame)| frame.join(", "));
+        assert_eq!(
+            frames.collect::<Vec<_>>(),
+            [
+                "KEY_A 1, SYN_REPORT 0",
+                "KEY_A 0, SYN_REPORT 0",
+                "KEY_B 1, SYN_REPORT 0",
+                "KEY_B 0, SYN_REPORT 0",
+            ]
+        );
+    }
 }
@@ -0,0 +1,131 @@
+// XKB keysym names (as used in sway config) for the keys on a typical keyboard, so they can
+// be used instead of evdev key names. A keysym depends on the layout, so it's looked up in the
+// current keymap when libxkbcommon is available. The mapping here is for the US layout, which
+// is what the physical keys are named after anyway.
+
+use std::str::FromStr;
+
+use evdev_rs::enums::EV_KEY;
+
+const KEYSYMS: &[(&str, EV_KEY)] = &[
+    ("Super_L", EV_KEY::KEY_LEFTMETA),
+    ("Super_R", EV_KEY::KEY_RIGHTMETA),
+    // The pc keymap puts Meta on the Alt keys (Alt+Shift), not on the Super ones
+    ("Meta_L", EV_KEY::KEY_LEFTALT),
+    ("Meta_R", EV_KEY::KEY_RIGHTALT),
+    ("Alt_L", EV_KEY::KEY_LEFTALT),
+    ("Alt_R", EV_KEY::KEY_RIGHTALT),
+    ("ISO_Level3_Shift", EV_KEY::KEY_RIGHTALT),
+    ("Control_L", EV_KEY::KEY_LEFTCTRL),
+    ("Control_R", EV_KEY::KEY_RIGHTCTRL),
+    ("Shift_L", EV_KEY::KEY_LEFTSHIFT),
+    ("Shift_R", EV_KEY::KEY_RIGHTSHIFT),
+    ("Caps_Lock", EV_KEY::KEY_CAPSLOCK),
+    ("Menu", EV_KEY::KEY_COMPOSE),
+    ("Tab", EV_KEY::KEY_TAB),
+    ("ISO_Left_Tab", EV_KEY::KEY_TAB),
+    ("Escape", EV_KEY::KEY_ESC),
+    ("Return", EV_KEY::KEY_ENTER),
+    ("space", EV_KEY::KEY_SPACE),
+    ("BackSpace", EV_KEY::KEY_BACKSPACE),
+    ("Delete", EV_KEY::KEY_DELETE),
+    ("Insert", EV_KEY::KEY_INSERT),
+    ("Home", EV_KEY::KEY_HOME),
+    ("End", EV_KEY::KEY_END),
+    ("Prior", EV_KEY::KEY_PAGEUP),
+    ("Page_Up", EV_KEY::KEY_PAGEUP),
+    ("Next", EV_KEY::KEY_PAGEDOWN),
+    ("Page_Down", EV_KEY::KEY_PAGEDOWN),
+    ("Left", EV_KEY::KEY_LEFT),
+    ("Right", EV_KEY::KEY_RIGHT),
+    ("Up", EV_KEY::KEY_UP),
+    ("Down", EV_KEY::KEY_DOWN),
+    ("Print", EV_KEY::KEY_SYSRQ),
+    ("Scroll_Lock", EV_KEY::KEY_SCROLLLOCK),
+    ("Pause", EV_KEY::KEY_PAUSE),
+    ("Num_Lock", EV_KEY::KEY_NUMLOCK),
+    ("grave", EV_KEY::KEY_GRAVE),
+    ("minus", EV_KEY::KEY_MINUS),
+    ("equal", EV_KEY::KEY_EQUAL),
+    ("bracketleft", EV_KEY::KEY_LEFTBRACE),
+    ("bracketright", EV_KEY::KEY_RIGHTBRACE),
+    ("backslash", EV_KEY::KEY_BACKSLASH),
+    ("semicolon", EV_KEY::KEY_SEMICOLON),
+    ("apostrophe", EV_KEY::KEY_APOSTROPHE),
+    ("comma", EV_KEY::KEY_COMMA),
+    ("period", EV_KEY::KEY_DOT),
+    ("slash", EV_KEY::KEY_SLASH),
+];
+
+// Returns the key for a keysym name, e.g. Super_L, Tab, a, 1 or F5
+pub fn keysym_to_key(name: &str) -> Option<EV_KEY> {
+    crate::xkb::keymap()
+        .and_then(|keymap| keymap.key_for(name))
+        .or_else(|| lookup(name))
+}
+
+// Returns the key with the keysym on the US layout
+fn lookup(name: &str) -> Option<EV_KEY> {
+    if let Some(&(_, key)) = KEYSYMS.iter().find(|(keysym, _)| *keysym == name) {
+        return Some(key);
+    }
+
+    // Letters, digits and function keys are named the same way in both namespaces
+    let is_letter_or_digit = name.len() == 1 && name.chars().all(|c| c.is_ascii_alphanumeric());
+    let is_function_key = name.starts_with('F') && name[1..].parse::<u8>().is_ok();
+    if is_letter_or_digit || is_function_key {
+        return EV_KEY::from_str(&format!("KEY_{}", name.to_ascii_uppercase())).ok();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use evdev_rs::enums::EV_KEY::*;
+
+    use super::*;
+    use crate::xkb::{Keymap, RuleNames};
+
+    #[test]
+    fn keysyms_are_looked_up_on_the_us_layout() {
+        let pairs = [
+            ("Super_L", KEY_LEFTMETA),
+            ("Meta_L", KEY_LEFTALT),
+            ("Alt_L", KEY_LEFTALT),
+            ("Control_R", KEY_RIGHTCTRL),
+            ("Tab", KEY_TAB),
+            ("Escape", KEY_ESC),
+            ("Return", KEY_ENTER),
+            ("grave", KEY_GRAVE),
+            ("a", KEY_A),
+            ("1", KEY_1),
+            ("F5", KEY_F5),
+        ];
+        for (keysym, key) in pairs {
+            assert_eq!(lookup(keysym), Some(key), "{keysym}");
+        }
+        assert_eq!(lookup("Super_X"), None);
+        assert_eq!(lookup("Fx"), None);
+    }
+
+    #[test]
+    fn table_agrees_with_the_us_keymap() {
+        // Pinned to the US layout without options, so the environment doesn't matter
+        let names = RuleNames {
+            layout: Some("us".to_string()),
+            variant: Some(String::new()),
+            options: Some(String::new()),
+        };
+        let Ok(keymap) = (unsafe { Keymap::load(&names) }) else {
+            // libxkbcommon isn't installed
+            return;
+        };
+        // Some keysyms (e.g. ISO_Level3_Shift) are only on the other layouts
+        for &(keysym, key) in KEYSYMS {
+            if let Some(keymap_key) = keymap.key_for(keysym) {
+                assert_eq!(keymap_key, key, "{keysym}");
+            }
+        }
+        assert_eq!(keymap.key_for("Meta_L"), Some(KEY_LEFTALT));
+    }
+}
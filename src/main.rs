@@ -3,25 +3,159 @@ use std::os::fd::AsRawFd;
 
 use clap::Parser;
 
+mod bindings;
+mod clock;
+mod devices;
+mod diagnostics;
+mod gestures;
 mod interceptor;
+mod keysyms;
+mod notifier;
+mod output;
+mod privileges;
+mod scheduling;
+mod signals;
+mod sleep;
+mod source;
+mod state;
 mod switcher;
+mod template;
+mod virtual_keyboard;
+mod xkb;
 
-use interceptor::{AltTabInterceptor, KeyConfig};
-use switcher::AltTabWorkspaceSwitcher;
+use devices::DeviceMatcher;
+use interceptor::{
+    AltTabInterceptor, Binding, BindingAction, Chord, DeviceChord, KeyConfig, ScancodeKey,
+};
+use output::{OutputBackend, OutputConfig, UinputProperties};
+use source::{EvdevSource, EventSource, GrabPolicy, StdinSource};
+use switcher::{
+    AltTabWorkspaceSwitcher, Boundary, CycleMode, NewWorkspacePosition, SwitcherConfig,
+    VirtualEntry,
+};
+use template::CommandTemplate;
 
-fn parse_keycode(key: &str) -> Result<evdev_rs::enums::EV_KEY, &'static str> {
-    <evdev_rs::enums::EV_KEY as std::str::FromStr>::from_str(key).map_err(|_| "no such key code")
+// Accepts evdev key names (KEY_TAB), XKB keysym names (Tab) and numeric keycodes (15). Single
+// digits are keysyms of the digit keys, the keycodes below 10 all have names anyway.
+pub fn parse_keycode(key: &str) -> Result<evdev_rs::enums::EV_KEY, &'static str> {
+    if let (true, Ok(code)) = (key.len() > 1, key.parse::<u32>()) {
+        evdev_rs::enums::int_to_ev_key(code).ok_or("no such numeric key code")
+    } else if key.starts_with("KEY_") || key.starts_with("BTN_") {
+        <evdev_rs::enums::EV_KEY as std::str::FromStr>::from_str(key)
+            .map_err(|_| "no such key code")
+    } else {
+        keysyms::keysym_to_key(key).ok_or("no such key code or keysym")
+    }
+}
+
+fn parse_on_switch(command: &str) -> Result<CommandTemplate, String> {
+    CommandTemplate::parse(command, switcher::ON_SWITCH_PLACEHOLDERS)
+}
+
+fn parse_switch_command(command: &str) -> Result<String, String> {
+    template::check_placeholders(command, switcher::SWITCH_COMMAND_PLACEHOLDERS)?;
+    Ok(command.to_string())
+}
+
+// Connects to the window manager's IPC socket, sway or i3
+pub fn connect_ipc() -> Result<swayipc::Connection, Box<dyn Error>> {
+    // swayipc panics if it can't find the socket path, look it up beforehand
+    let path = find_socket_path(|var| std::env::var_os(var), ask_socket_path).ok_or(
+        "neither SWAYSOCK nor I3SOCK is set and neither sway nor i3 tells its socket path, \
+        sway or i3 should be running",
+    )?;
+    let stream = std::os::unix::net::UnixStream::connect(&path).map_err(|e| {
+        format!(
+            "sway (or i3) IPC socket ({}) should be available for connection: {e}",
+            path.display()
+        )
+    })?;
+    Ok(swayipc::Connection::from(stream))
+}
+
+// The socket path from I3SOCK or SWAYSOCK, which i3 and sway set for the programs they start,
+// or else from `sway --get-socketpath` or `i3 --get-socketpath` (e.g. in a systemd service)
+fn find_socket_path(
+    var: impl Fn(&str) -> Option<std::ffi::OsString>,
+    ask: impl Fn(&str) -> Option<std::path::PathBuf>,
+) -> Option<std::path::PathBuf> {
+    ["I3SOCK", "SWAYSOCK"]
+        .into_iter()
+        .filter_map(var)
+        .find(|path| !path.is_empty())
+        .map(std::path::PathBuf::from)
+        .or_else(|| ["sway", "i3"].into_iter().find_map(ask))
+}
+
+fn ask_socket_path(wm: &str) -> Option<std::path::PathBuf> {
+    let output = std::process::Command::new(wm)
+        .arg("--get-socketpath")
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let path = String::from_utf8(output.stdout).ok()?;
+    let path = path.trim();
+    (!path.is_empty()).then(|| path.into())
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// List the input devices (/dev/input/eventN) with their names and capabilities and exit
+    ListDevices,
 }
 
 #[derive(clap::Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
+#[command(args_override_self = true)]
 struct Cli {
-    // TODO: make optional, try to autodetect if not given
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(
-        help = "The keyboard input device path to use for intercepting keypresses\n\
-        (/dev/input/eventN or other)"
+        help = "The keyboard input device paths to use for intercepting keypresses\n\
+        (/dev/input/eventN or other), all of them are intercepted at once. The paths can have\n\
+        * and ? wildcards in the file name, e.g. 'by-id/*-kbd' (relative to /dev/input).\n\
+        If none are given, the keyboard with the configured keys is detected"
     )]
-    input_device: std::path::PathBuf,
+    input_devices: Vec<std::path::PathBuf>,
+
+    #[arg(long, conflicts_with = "input_devices")]
+    /// Use the input device with this name instead of a path (see `cat /proc/bus/input/devices`),
+    /// * and ? wildcards are allowed
+    device_name: Option<String>,
+
+    #[arg(long, value_parser = devices::parse_usb_id, conflicts_with = "input_devices")]
+    /// Use the input device with this hexadecimal vendor id instead of a path
+    vendor: Option<u16>,
+
+    #[arg(long, value_parser = devices::parse_usb_id, conflicts_with = "input_devices")]
+    /// Use the input device with this hexadecimal product id instead of a path
+    product: Option<u16>,
+
+    #[arg(long = "pointer-device", value_name = "PATH")]
+    /// Intercept this pointer device (/dev/input/eventN) too, so its buttons (e.g. BTN_SIDE)
+    /// can be used in the shortcut, can be repeated
+    pointer_devices: Vec<std::path::PathBuf>,
+
+    #[arg(long)]
+    /// Scrolling the mouse wheel (of a --pointer-device) with the modifier held cycles
+    /// through the workspaces: down selects the next one, up - the previous one
+    scroll_wheel: bool,
+
+    #[arg(long, value_name = "PATH")]
+    /// Cycle through the workspaces with horizontal three-finger swipes on this touchpad
+    /// (/dev/input/eventN), it's not grabbed
+    gesture_device: Option<std::path::PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["input_devices", "device_name", "vendor", "product", "pointer_devices"]
+    )]
+    /// Read key events from stdin instead of a keyboard, one "<KEY_NAME> <0|1|2>" per line
+    /// (to try out a key configuration without grabbing the keyboard)
+    stdin: bool,
 
     #[arg(
         short, long,
@@ -40,89 +174,852 @@ struct Cli {
     )]
     /// The second key in the Alt-Tab seqence (trigger)
     trigger: evdev_rs::enums::EV_KEY,
+
+    #[arg(long, value_name = "MOD+MOD,TRIGGER", conflicts_with_all = ["modifiers", "trigger"])]
+    /// A chord with any number of modifiers which are all held down, e.g.
+    /// KEY_LEFTCTRL+KEY_LEFTALT,KEY_TAB, instead of --modifiers and --trigger
+    chord: Option<Chord>,
+
+    #[arg(long, value_parser = parse_keycode)]
+    /// A second trigger which cycles the windows of the focused workspace instead of the
+    /// workspaces (e.g. KEY_GRAVE)
+    window_trigger: Option<evdev_rs::enums::EV_KEY>,
+
+    #[arg(long = "device-chord", value_name = "PATH=CHORD")]
+    /// Intercept this input device separately, with its own chord in the --chord format
+    /// instead of the main shortcut, e.g. /dev/input/event3=KEY_CAPSLOCK,KEY_TAB, can be
+    /// repeated
+    device_chords: Vec<DeviceChord>,
+
+    #[arg(long = "gamepad", value_name = "PATH", conflicts_with = "stdin")]
+    /// A game controller whose right and left shoulder buttons (BTN_TR, BTN_TL) select the
+    /// next and the previous workspace while --gamepad-modifier is held. It isn't grabbed, so
+    /// the buttons still reach the games. Can be repeated
+    gamepads: Vec<std::path::PathBuf>,
+
+    #[arg(long, value_parser = parse_keycode, default_value = "BTN_MODE")]
+    /// The game controller button held while pressing the shoulder buttons
+    gamepad_modifier: evdev_rs::enums::EV_KEY,
+
+    #[arg(long = "bind", value_name = "CHORD=ACTION")]
+    /// An additional chord in the --chord format and its action (cycle-forward, cycle-back,
+    /// window-forward, window-back, jump-to-last or cancel), e.g.
+    /// KEY_LEFTALT,KEY_GRAVE=jump-to-last, can be repeated
+    bindings: Vec<Binding>,
+
+    #[arg(long, value_name = "FILE")]
+    /// A file with more options, one or more per line as on the command line (without
+    /// quoting), e.g. `--chord KEY_LEFTALT,KEY_TAB`. The key options in it are reloaded on
+    /// SIGHUP, overriding the ones from the command line
+    key_config: Option<std::path::PathBuf>,
+
+    #[arg(long, value_parser = parse_keycode, default_value = "KEY_LEFTSHIFT")]
+    /// Holding this key together with the modifier makes the trigger select the previous
+    /// workspace (or window) instead of the next one
+    reverse_modifier: evdev_rs::enums::EV_KEY,
+
+    #[arg(long, value_parser = parse_keycode)]
+    /// Holding this key together with the modifier makes the trigger move the focused window
+    /// along to the selected workspace
+    move_modifier: Option<evdev_rs::enums::EV_KEY>,
+
+    #[arg(long, value_name = "MS")]
+    /// Keep advancing through the list while the trigger is held, at most once per this
+    /// interval (limited by the keyboard's autorepeat rate)
+    trigger_repeat_interval: Option<u64>,
+
+    #[arg(long, value_name = "MS")]
+    /// Double-tapping the modifier alone within this time switches to the previous workspace
+    double_tap: Option<u64>,
+
+    #[arg(long, value_name = "MS")]
+    /// Commit the selection and end the sequence after this time without key presses, even if
+    /// the modifier is still held
+    sequence_timeout: Option<u64>,
+
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    /// Treat a modifier release followed by a re-press within this time as a continuous hold
+    /// (for flaky keyboards or remappers)
+    modifier_release_grace: u64,
+
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    /// Ignore a trigger press coming this soon after the previous one, as a bounce of a worn
+    /// out key
+    trigger_debounce: u64,
+
+    #[arg(long, value_parser = parse_keycode)]
+    /// A key which pins the selected workspace right after the focused one (or unpins it)
+    /// when pressed during the sequence
+    pin_key: Option<evdev_rs::enums::EV_KEY>,
+
+    #[arg(long, value_parser = parse_keycode)]
+    /// A key which removes the selected workspace from the list when pressed during the
+    /// sequence, until it's focused again
+    remove_key: Option<evdev_rs::enums::EV_KEY>,
+
+    #[arg(long, value_parser = parse_keycode, default_value = "KEY_ESC")]
+    /// A key which cancels the sequence when pressed during it, going back to the workspace
+    /// it has started on
+    cancel_key: evdev_rs::enums::EV_KEY,
+
+    #[arg(long = "scancode", value_name = "SCANCODE=KEY")]
+    /// Treat a key pressed with this hardware scancode (MSC_SCAN, hexadecimal with 0x) as KEY
+    /// when detecting the chord, whatever key code it's reported with (e.g. after a remapper),
+    /// can be repeated
+    scancode_keys: Vec<ScancodeKey>,
+
+    #[arg(long)]
+    /// Pressing a number key (1-9) during the sequence switches to the entry at that position
+    /// in the list at once, 1 being the previous workspace
+    jump_keys: bool,
+
+    #[arg(long)]
+    /// Only trigger if the trigger has been pressed after all modifiers of its chord, e.g. its
+    /// autorepeat stops cycling when a modifier is released and pressed again while it's held
+    strict_chord_order: bool,
+
+    #[arg(
+        long,
+        value_name = "COMMAND",
+        value_parser = parse_switch_command,
+        default_value = "workspace {name}"
+    )]
+    /// The sway command used to switch to a workspace, {name}, {num} and {id} placeholders
+    /// are replaced with the workspace's properties
+    switch_command: String,
+
+    #[arg(long, value_name = "COMMAND", value_parser = parse_on_switch)]
+    /// A command to run after each workspace switch, {workspace}, {output} and {con_id}
+    /// placeholders are replaced with the target workspace's properties
+    on_switch: Option<CommandTemplate>,
+
+    #[arg(long, value_enum, default_value_t = CycleMode::Mru)]
+    /// The order to cycle through the workspaces in: most recently used first, or the order
+    /// the workspaces were created in. Or cycle through the windows of all workspaces instead,
+    /// most recently focused first
+    mode: CycleMode,
+
+    #[arg(long, value_enum, default_value_t = Boundary::Wrap)]
+    /// What the trigger does on the last workspace in the list: go back to the first one or
+    /// stay on the last one. clamp-flash stays too, and tells the webhook about it
+    boundary: Boundary,
+
+    #[arg(long, value_enum, default_value_t = NewWorkspacePosition::Back)]
+    /// Where to place newly created workspaces in the list: first, even ahead of the focused
+    /// workspace (`front`), right after the focused workspace (`second`), or at the end.
+    /// Focusing a workspace moves it to the front anyway
+    new_workspace_position: NewWorkspacePosition,
+
+    #[arg(long)]
+    /// Switch to the selected workspace only when the modifier is released, not on each
+    /// trigger press
+    preview: bool,
+
+    #[arg(long)]
+    /// Don't let other programs changing the focused workspace interrupt the sequence,
+    /// switch back to the selected workspace instead
+    ignore_external_focus: bool,
+
+    #[cfg(feature = "webhook")]
+    #[arg(long, value_name = "URL")]
+    /// POST {"workspace": "<name>"} to this http:// URL after each switch
+    webhook: Option<notifier::WebhookNotifier>,
+
+    #[arg(long)]
+    /// Move the mouse cursor to the center of the output when switching to a workspace on
+    /// another output
+    warp_cursor: bool,
+
+    #[arg(long = "exclude-output", value_name = "OUTPUT")]
+    /// Never switch to workspaces on this output (e.g. HDMI-A-2), can be repeated
+    excluded_outputs: Vec<String>,
+
+    #[arg(long = "ignore-workspace", value_name = "REGEX")]
+    /// Never switch to workspaces with names matching this regular expression (e.g.
+    /// "^bg-.*"), can be repeated
+    ignored_workspaces: Vec<regex::Regex>,
+
+    #[arg(long, value_name = "FILE")]
+    /// Where the most recently used order of the workspaces is kept across restarts,
+    /// $XDG_STATE_HOME/sway-alttab/state.json by default
+    state_file: Option<std::path::PathBuf>,
+
+    #[arg(long, conflicts_with = "state_file")]
+    /// Start with a fresh workspace order each time, without a state file
+    no_state_file: bool,
+
+    #[arg(long, visible_alias = "current-output-only")]
+    /// Cycle only through the workspaces of the focused output, skipping the others, so the
+    /// focus doesn't jump across monitors. Each output keeps its own most recently used order
+    per_output: bool,
+
+    #[arg(long = "virtual-entry", value_name = "LABEL=COMMAND")]
+    /// Add an entry to the list of workspaces, which runs a sway command when switched to
+    /// instead (e.g. "scratch=scratchpad show"), can be repeated
+    virtual_entries: Vec<VirtualEntry>,
+
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "input_devices", "stdin", "device_name", "vendor", "product", "pointer_devices",
+            "device_chords", "gamepads", "check"
+        ]
+    )]
+    /// Don't intercept the keyboard, react to `nop alttab ...` bindings in sway config
+    /// instead (see the README), no input device access is needed
+    sway_bindings: bool,
+
+    #[arg(long, value_enum, default_value_t = OutputBackend::Uinput, conflicts_with = "stdin")]
+    /// Where to send the keys which aren't intercepted: a uinput device, or the compositor's
+    /// virtual keyboard protocol (doesn't need access to /dev/uinput)
+    output: OutputBackend,
+
+    #[arg(long, value_name = "NAME", conflicts_with = "stdin")]
+    /// Name of the uinput output device, instead of the input device's one (e.g. to tell it
+    /// apart in sway's input rules)
+    uinput_name: Option<String>,
+
+    #[arg(long, value_name = "ID", value_parser = devices::parse_usb_id, conflicts_with = "stdin")]
+    /// Hexadecimal vendor id of the uinput output device
+    uinput_vendor: Option<u16>,
+
+    #[arg(long, value_name = "ID", value_parser = devices::parse_usb_id, conflicts_with = "stdin")]
+    /// Hexadecimal product id of the uinput output device
+    uinput_product: Option<u16>,
+
+    #[arg(long, conflicts_with = "stdin")]
+    /// Make the uinput output device a keyboard only, without the input device's other
+    /// capabilities (mouse axes, LEDs, ...)
+    uinput_keys_only: bool,
+
+    #[arg(long, conflicts_with = "stdin")]
+    /// Check that the environment is set up correctly (the input device, uinput and
+    /// sway IPC are accessible) and exit
+    check: bool,
+
+    #[arg(long, conflicts_with_all = ["stdin", "sway_bindings", "monitor"])]
+    /// Grab the input devices only while the modifier is held instead of all the time, so
+    /// the other programs reading them are less affected
+    lazy_grab: bool,
+
+    #[arg(long, conflicts_with_all = ["check", "sway_bindings"])]
+    /// Watch the input devices without grabbing them and log which events would be swallowed
+    /// and which switches would happen, nothing is switched or forwarded
+    monitor: bool,
+
+    #[arg(
+        long,
+        value_name = "PRIORITY",
+        value_parser = clap::value_parser!(i32).range(1..=99),
+        conflicts_with_all = ["check", "sway_bindings"]
+    )]
+    /// Run the keypress interception with this real-time (SCHED_FIFO) priority, 1-99, to keep
+    /// the typing latency low under load. Without the permission for it, the niceness is
+    /// raised instead if possible
+    realtime_priority: Option<i32>,
+
+    #[arg(long)]
+    /// When started as root, switch to this user after opening the input devices. The input
+    /// group is kept to reopen the keyboards, the uinput device isn't recreated after errors
+    user: Option<String>,
 }
 
 pub enum WorkspaceSwitcherEvent {
     Trigger,
+    // The trigger pressed with the reverse modifier, selects the previous workspace
+    TriggerBack,
+    // The trigger pressed with the move modifier, the focused container is carried along to
+    // the selected workspace
+    TriggerMove,
+    TriggerMoveBack,
+    // The window trigger, selects the next (or the previous) window of the focused workspace
+    WindowTrigger,
+    WindowTriggerBack,
     EndMod,
+    // Pin or unpin the selected workspace right after the focused one
+    PinCurrent,
+    // Remove the selected workspace from the list until it's focused again
+    RemoveCurrent,
+    // Go back to where the sequence has started and end it
+    Cancel,
+    // Switch to the entry at this position in the list (1 is the previous workspace) and end
+    // the sequence
+    JumpTo(usize),
     SwayWsEvent(Box<swayipc::WorkspaceEvent>),
+    // Only subscribed to in the windows mode
+    SwayWindowEvent(Box<swayipc::WindowEvent>),
 }
 
 impl std::fmt::Debug for WorkspaceSwitcherEvent {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Trigger => f.write_str("Trigger"),
+            Self::TriggerBack => f.write_str("TriggerBack"),
+            Self::TriggerMove => f.write_str("TriggerMove"),
+            Self::TriggerMoveBack => f.write_str("TriggerMoveBack"),
+            Self::WindowTrigger => f.write_str("WindowTrigger"),
+            Self::WindowTriggerBack => f.write_str("WindowTriggerBack"),
             Self::EndMod => f.write_str("EndMod"),
+            Self::PinCurrent => f.write_str("PinCurrent"),
+            Self::RemoveCurrent => f.write_str("RemoveCurrent"),
+            Self::Cancel => f.write_str("Cancel"),
+            Self::JumpTo(n) => f.write_fmt(format_args!("JumpTo({n})")),
             Self::SwayWsEvent(evt) => {
                 // Default debug output for WorkspaceEvent is too large, display only the change type
                 f.write_fmt(format_args!("SwayWsEvent({:?})", evt.as_ref().change))
             }
+            Self::SwayWindowEvent(evt) => {
+                f.write_fmt(format_args!("SwayWindowEvent({:?})", evt.as_ref().change))
+            }
+        }
+    }
+}
+
+// Sway IPC event types to subscribe to, each of them must be routed in route_sway_event()
+const SWAY_EVENT_TYPES: [swayipc::EventType; 1] = [swayipc::EventType::Workspace];
+
+// Converts a sway IPC event to the corresponding switcher event, None if it's not needed.
+// Binding events are only subscribed to (and routed) with --sway-bindings, window events in
+// the windows mode.
+fn route_sway_event(
+    evt: swayipc::Event,
+    bindings: Option<&mut bindings::BindingRouter>,
+) -> Option<WorkspaceSwitcherEvent> {
+    match evt {
+        swayipc::Event::Workspace(evt) => Some(WorkspaceSwitcherEvent::SwayWsEvent(evt)),
+        swayipc::Event::Window(evt) => Some(WorkspaceSwitcherEvent::SwayWindowEvent(evt)),
+        swayipc::Event::Binding(evt) => bindings?.route(&evt),
+        // Shouldn't happen unless SWAY_EVENT_TYPES and this match get out of sync
+        evt => {
+            log::trace!("Ignoring unexpected sway event: {:?}", evt);
+            None
         }
     }
 }
 
+// A panic in any thread takes the whole process down, even if built with panic = "unwind".
+// The kernel then releases the keyboard grab and destroys the uinput device, which releases
+// the keys pressed through it, so the keyboard is never left grabbed by a half-dead process.
+// The same happens on SIGINT/SIGTERM, which terminate the process by default.
+fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let thread = std::thread::current();
+        log::error!(
+            "Thread \"{}\" panicked, exiting: {info}",
+            thread.name().unwrap_or("unnamed")
+        );
+        default_hook(info);
+        std::process::abort();
+    }));
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     env_logger::builder()
         .filter_level(log::LevelFilter::Info)
         .parse_default_env()
         .init();
+    install_panic_hook();
+    signals::install()?;
 
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+    if let Some(path) = cli.key_config.clone() {
+        cli = parse_with_key_config(&path)?;
+    }
     log::debug!("Parsed arguments: {:#?}", cli);
+    if let Some(Command::ListDevices) = cli.command {
+        return devices::list_devices();
+    }
     let (tx, rx) = std::sync::mpsc::channel::<WorkspaceSwitcherEvent>();
 
-    // When user presses enter to run this program in a terminal, the press
-    // event is sent from the real keyboard, but the release event is sent
-    // from the fake uinput device, creating a stream of repeated enter presses.
-    // The delay is to make sure the release event is sent correctly.
-    let interactive = unsafe { libc::isatty(std::io::stdin().as_raw_fd()) == 1 };
-    if interactive {
-        log::debug!("Performing a 500ms delay because running interactively...");
-        std::thread::sleep(std::time::Duration::from_millis(500));
-    }
-
-    let input_device_path = cli.input_device;
-    let mut interceptor = AltTabInterceptor::new(
-        &input_device_path,
-        tx.clone(),
-        KeyConfig {
-            modifier1: cli.modifiers[0],
-            modifier2: cli.modifiers.get(1).copied(),
-            trigger: cli.trigger,
+    let (key_config, device_key_configs) = key_configs(&cli);
+    let mut configured_keys = Vec::new();
+    let all_key_configs = std::iter::once(&key_config)
+        .chain(device_key_configs.iter().map(|(_, config)| config))
+        .collect::<Vec<_>>();
+    let bound_keys = all_key_configs
+        .iter()
+        .flat_map(|config| config.modifier_keys().chain(config.trigger_keys()));
+    for key in bound_keys
+        .chain([cli.reverse_modifier])
+        .chain(cli.move_modifier)
+        .chain(cli.pin_key)
+        .chain(cli.remove_key)
+        .chain([cli.cancel_key])
+    {
+        if !configured_keys.contains(&key) {
+            configured_keys.push(key);
+        }
+    }
+    // The paths with wildcards are matched like the name, including the devices plugged in later
+    let (path_patterns, mut input_devices): (Vec<_>, Vec<_>) = cli
+        .input_devices
+        .into_iter()
+        .partition(|path| devices::is_pattern(&path.to_string_lossy()));
+    let device_matcher = DeviceMatcher {
+        name: cli.device_name,
+        vendor: cli.vendor,
+        product: cli.product,
+        paths: path_patterns
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect(),
+    };
+
+    // Without any devices given (including the game controllers), the keyboard is detected
+    if input_devices.is_empty()
+        && device_matcher.is_empty()
+        && !cli.stdin
+        && !cli.sway_bindings
+        && cli.device_chords.is_empty()
+        && cli.gamepads.is_empty()
+    {
+        let keys = key_config
+            .modifier_keys()
+            .chain(key_config.trigger_keys())
+            .collect::<Vec<_>>();
+        input_devices.push(devices::detect_keyboard(&keys)?);
+    }
+
+    if cli.check {
+        let device_chord_paths = device_key_configs.iter().map(|(path, _)| path.clone());
+        let passed = diagnostics::run_checks(
+            &input_devices
+                .iter()
+                .cloned()
+                .chain(device_chord_paths)
+                .collect::<Vec<_>>(),
+            &device_matcher,
+            &cli.pointer_devices,
+            &all_key_configs,
+            &configured_keys,
+            cli.output,
+        );
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+
+    let grab_policy = match (cli.monitor, cli.lazy_grab) {
+        (true, _) => GrabPolicy::Never,
+        (false, true) => GrabPolicy::Lazy,
+        (false, false) => GrabPolicy::Always,
+    };
+    let output = OutputConfig {
+        backend: cli.output,
+        uinput: UinputProperties {
+            name: cli.uinput_name,
+            vendor: cli.uinput_vendor,
+            product: cli.uinput_product,
+            keys_only: cli.uinput_keys_only,
         },
-    )?;
+    };
+    // Without the main devices, only the ones with their own chords are intercepted
+    let has_main_devices = cli.stdin
+        || !input_devices.is_empty()
+        || !device_matcher.is_empty()
+        || device_key_configs.is_empty();
+    let mut interceptors = Vec::new();
+    if !cli.sway_bindings && has_main_devices {
+        let mut interceptor = create_interceptor(
+            input_devices,
+            &device_matcher,
+            cli.pointer_devices,
+            key_config,
+            output.clone(),
+            grab_policy,
+            tx.clone(),
+        )?;
+        if let Some(path) = &cli.key_config {
+            interceptor.set_key_config_loader(key_config_loader(path.clone(), None));
+        }
+        interceptors.push(interceptor);
+    }
+    for (device, key_config) in device_key_configs {
+        // The game controllers are shared with the games
+        let grab = match grab_policy {
+            GrabPolicy::Never => GrabPolicy::Never,
+            _ if cli.gamepads.contains(&device) => GrabPolicy::Shared,
+            grab_policy => grab_policy,
+        };
+        let mut interceptor = create_interceptor(
+            vec![device.clone()],
+            &DeviceMatcher::default(),
+            Vec::new(),
+            key_config,
+            output.clone(),
+            grab,
+            tx.clone(),
+        )?;
+        if let Some(path) = &cli.key_config {
+            interceptor.set_key_config_loader(key_config_loader(path.clone(), Some(device)));
+        }
+        interceptors.push(interceptor);
+    }
+
+    if cli.monitor {
+        log::info!("Monitoring the input devices, press Ctrl+C to stop");
+        spawn_interceptors(interceptors, cli.realtime_priority, || Ok(()))?;
+        drop(tx);
+        for evt in rx {
+            log::info!("Would send to the switcher: {evt:?}");
+        }
+        return Ok(());
+    }
+
+    let gestures = cli
+        .gesture_device
+        .map(|path| gestures::GestureSource::open(&path, tx.clone()))
+        .transpose()?;
 
+    // The input devices are open now, only the uinput device can't be created again without root
+    let mut devices_reopenable = true;
+    let mut drop_privileges = || -> Result<(), Box<dyn Error>> {
+        let Some(user) = &cli.user else {
+            return Ok(());
+        };
+        devices_reopenable = privileges::drop_privileges(user)?;
+        if !devices_reopenable {
+            log::warn!(
+                "There's no input group, the keyboards can't be reopened after they're \
+                unplugged or fail, and stay open during suspend"
+            );
+        }
+        Ok(())
+    };
+    if cli.sway_bindings {
+        drop_privileges()?;
+    } else {
+        if cli.user.is_some() {
+            for interceptor in &mut interceptors {
+                interceptor.disable_uinput_recreation();
+            }
+        }
+        // Raising the priority may need root too, so the interceptors start before the drop
+        spawn_interceptors(interceptors, cli.realtime_priority, drop_privileges)?;
+    }
+
+    let mut conn = connect_ipc()?;
+
+    let notifier: Box<dyn notifier::Notifier> = {
+        #[cfg(feature = "webhook")]
+        match cli.webhook {
+            Some(webhook) => Box::new(webhook),
+            None => Box::new(notifier::NoopNotifier),
+        }
+        #[cfg(not(feature = "webhook"))]
+        Box::new(notifier::NoopNotifier)
+    };
+    let switcher_config = SwitcherConfig {
+        switch_command: cli.switch_command,
+        mode: cli.mode,
+        boundary: cli.boundary,
+        new_workspace_position: cli.new_workspace_position,
+        on_switch: cli.on_switch,
+        notifier,
+        preview: cli.preview,
+        ignore_external_focus: cli.ignore_external_focus,
+        virtual_entries: cli.virtual_entries,
+        warp_cursor: cli.warp_cursor,
+        excluded_outputs: cli.excluded_outputs,
+        per_output: cli.per_output,
+        ignored_workspaces: cli.ignored_workspaces,
+        state_file: match cli.no_state_file {
+            true => None,
+            false => cli.state_file.or_else(state::default_path),
+        },
+        sequence_timeout: cli.sequence_timeout.map(std::time::Duration::from_millis),
+    };
+    let switcher_ipc = Box::new(connect_ipc()?);
     std::thread::Builder::new()
         .name("workspace-switcher".to_string())
-        .spawn(move || AltTabWorkspaceSwitcher::new(rx).run())
+        .spawn(move || {
+            let clock = Box::new(clock::MonotonicClock);
+            AltTabWorkspaceSwitcher::new(rx, switcher_config, switcher_ipc, clock).run()
+        })
         .map_err(|e| format!("can't create workspace switcher thread: {e}"))?;
 
-    std::thread::Builder::new()
-        .name("interceptor".to_string())
-        .spawn(move || interceptor.run())
-        .map_err(|e| format!("can't create keypress interceptor thread: {e}"))?;
+    if let Some(mut gestures) = gestures {
+        std::thread::Builder::new()
+            .name("gestures".to_string())
+            .spawn(move || gestures.run())
+            .map_err(|e| format!("can't create gesture recognizer thread: {e}"))?;
+    }
 
-    let conn = swayipc::Connection::new()
-        .map_err(|e| format!("sway IPC socket should be available for connection: {e}"))?;
+    let mut event_types = SWAY_EVENT_TYPES.to_vec();
+    if cli.mode == CycleMode::Windows {
+        event_types.push(swayipc::EventType::Window);
+    }
+    let mut bindings = None;
+    if cli.sway_bindings {
+        log::info!("Using sway bindings instead of intercepting the keyboard");
+        bindings = Some(bindings::BindingRouter::new()?);
+        event_types.push(swayipc::EventType::Binding);
+    } else {
+        diagnostics::check_sway_remaps(&mut conn, &configured_keys);
+        if !cli.stdin && devices_reopenable {
+            if let Err(e) = sleep::watch() {
+                log::warn!("The keyboards won't be released during suspend: {e}");
+            }
+        }
+    }
     let evt_iter = conn
-        .subscribe([swayipc::EventType::Workspace])
-        .map_err(|e| format!("can't subscribe to sway IPC workspace events: {e}"))?;
+        .subscribe(event_types)
+        .map_err(|e| format!("can't subscribe to sway IPC events: {e}"))?;
 
-    // Forward sway workspace events to the switcher thread
+    // Forward sway events to the switcher thread
     for evt in evt_iter {
-        match evt {
-            Ok(swayipc::Event::Workspace(evt)) => {
-                tx.send(WorkspaceSwitcherEvent::SwayWsEvent(evt))
-                    .map_err(|e| {
-                        format!("can't send a sway workspace event, the channel is dead: {e}")
-                    })?;
-            }
-            Err(e) => {
-                return Err(format!("sway IPC listener error: {e}").into());
-            }
-            _ => {}
+        let evt = evt.map_err(|e| format!("sway IPC listener error: {e}"))?;
+        if let Some(evt) = route_sway_event(evt, bindings.as_mut()) {
+            tx.send(evt)
+                .map_err(|e| format!("can't send a sway event, the channel is dead: {e}"))?;
         }
     }
 
     panic!("Sway IPC connection has been closed");
 }
+
+// The command line followed by the options from the key config file, which override it
+fn parse_with_key_config(path: &std::path::Path) -> Result<Cli, Box<dyn Error>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("can't read the key config file ({}): {e}", path.display()))?;
+    let file_args = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .flat_map(str::split_whitespace)
+        .map(std::ffi::OsString::from);
+    Cli::try_parse_from(std::env::args_os().chain(file_args)).map_err(|e| {
+        format!(
+            "invalid options in the key config file ({}): {e}",
+            path.display()
+        )
+        .into()
+    })
+}
+
+// Re-reads the key config file for the interceptor of the device with its own chord, or the
+// main one if None
+fn key_config_loader(
+    path: std::path::PathBuf,
+    device: Option<std::path::PathBuf>,
+) -> interceptor::KeyConfigLoader {
+    Box::new(move || {
+        let (key_config, device_key_configs) = key_configs(&parse_with_key_config(&path)?);
+        let Some(device) = &device else {
+            return Ok(key_config);
+        };
+        device_key_configs
+            .into_iter()
+            .find(|(path, _)| path == device)
+            .map(|(_, key_config)| key_config)
+            .ok_or_else(|| format!("no --device-chord for {} anymore", device.display()).into())
+    })
+}
+
+// The key configuration of the main interceptor, and of the devices with their own chords
+fn key_configs(cli: &Cli) -> (KeyConfig, Vec<(std::path::PathBuf, KeyConfig)>) {
+    // The options describing the main shortcut are expanded to bindings, one per modifier
+    let (modifiers, trigger) = match &cli.chord {
+        Some(chord) => (vec![chord.modifiers.clone()], chord.trigger),
+        None => (
+            cli.modifiers.iter().map(|&key| vec![key]).collect(),
+            cli.trigger,
+        ),
+    };
+    let key_config = KeyConfig {
+        bindings: shortcut_bindings(&modifiers, trigger, cli.window_trigger, &cli.bindings),
+        reverse_modifier: cli.reverse_modifier,
+        move_modifier: cli.move_modifier,
+        trigger_repeat_interval: cli
+            .trigger_repeat_interval
+            .map(std::time::Duration::from_millis),
+        double_tap_window: cli.double_tap.map(std::time::Duration::from_millis),
+        modifier_release_grace: std::time::Duration::from_millis(cli.modifier_release_grace),
+        trigger_debounce: std::time::Duration::from_millis(cli.trigger_debounce),
+        strict_chord_order: cli.strict_chord_order,
+        pin_key: cli.pin_key,
+        remove_key: cli.remove_key,
+        cancel_key: cli.cancel_key,
+        jump_keys: cli.jump_keys,
+        scancode_keys: cli.scancode_keys.clone(),
+        scroll_wheel: cli.scroll_wheel,
+    };
+    // The devices with their own chords get their own interceptors, the rest of the key
+    // configuration is the same
+    let device_chords = cli.device_chords.iter().map(|DeviceChord { path, chord }| {
+        let modifiers = [chord.modifiers.clone()];
+        let bindings =
+            shortcut_bindings(&modifiers, chord.trigger, cli.window_trigger, &cli.bindings);
+        (path.clone(), bindings)
+    });
+    // So do the game controllers, with the shoulder buttons as the triggers
+    let gamepads = cli.gamepads.iter().map(|path| {
+        let bindings = [
+            (evdev_rs::enums::EV_KEY::BTN_TR, BindingAction::CycleForward),
+            (evdev_rs::enums::EV_KEY::BTN_TL, BindingAction::CycleBack),
+        ]
+        .map(|(key, action)| Binding {
+            modifiers: vec![cli.gamepad_modifier],
+            key,
+            action,
+        });
+        (path.clone(), bindings.to_vec())
+    });
+    let device_key_configs = device_chords
+        .chain(gamepads)
+        .map(|(path, bindings)| {
+            (
+                path,
+                KeyConfig {
+                    bindings,
+                    ..key_config.clone()
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+    (key_config, device_key_configs)
+}
+
+// Expands a shortcut to bindings, one per modifier combination, followed by the extra ones
+fn shortcut_bindings(
+    modifiers: &[Vec<evdev_rs::enums::EV_KEY>],
+    trigger: evdev_rs::enums::EV_KEY,
+    window_trigger: Option<evdev_rs::enums::EV_KEY>,
+    extra: &[Binding],
+) -> Vec<Binding> {
+    let triggers = std::iter::once((trigger, BindingAction::CycleForward))
+        .chain(window_trigger.map(|key| (key, BindingAction::WindowForward)));
+    triggers
+        .flat_map(|(key, action)| {
+            modifiers.iter().map(move |modifiers| Binding {
+                modifiers: modifiers.clone(),
+                key,
+                action,
+            })
+        })
+        .chain(extra.iter().cloned())
+        .collect()
+}
+
+// The interceptors raise their priority first, then wait until before_start is done, e.g. until
+// the privileges are dropped
+fn spawn_interceptors(
+    interceptors: Vec<AltTabInterceptor>,
+    realtime_priority: Option<i32>,
+    before_start: impl FnOnce() -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let barrier = std::sync::Arc::new(std::sync::Barrier::new(interceptors.len() + 1));
+    for mut interceptor in interceptors {
+        let barrier = barrier.clone();
+        std::thread::Builder::new()
+            .name("interceptor".to_string())
+            .spawn(move || {
+                if let Some(priority) = realtime_priority {
+                    scheduling::raise_priority(priority);
+                }
+                // Once when the priority is raised, once when it's time to start
+                barrier.wait();
+                barrier.wait();
+                interceptor.run()
+            })
+            .map_err(|e| format!("can't create keypress interceptor thread: {e}"))?;
+    }
+    barrier.wait();
+    before_start()?;
+    barrier.wait();
+    Ok(())
+}
+
+// Opens the input devices (or stdin) and sets up the interceptor for them, when monitoring
+// (GrabPolicy::Never) there's no output device
+fn create_interceptor(
+    input_devices: Vec<std::path::PathBuf>,
+    device_matcher: &DeviceMatcher,
+    pointer_devices: Vec<std::path::PathBuf>,
+    key_config: KeyConfig,
+    output: OutputConfig,
+    grab: GrabPolicy,
+    tx: std::sync::mpsc::Sender<WorkspaceSwitcherEvent>,
+) -> Result<AltTabInterceptor, Box<dyn Error>> {
+    let mut input_devices = device_matcher.resolve(&input_devices)?;
+    if !input_devices.is_empty() {
+        input_devices.extend(pointer_devices);
+    }
+    let source: Box<dyn EventSource> = match input_devices.is_empty() {
+        false => {
+            // When user presses enter to run this program in a terminal, the press
+            // event is sent from the real keyboard, but the release event is sent
+            // from the fake output device, creating a stream of repeated enter presses.
+            // The delay is to make sure the release event is sent correctly.
+            let interactive = unsafe { libc::isatty(std::io::stdin().as_raw_fd()) == 1 };
+            if interactive && grab == GrabPolicy::Always {
+                log::debug!("Performing a 500ms delay because running interactively...");
+                std::thread::sleep(std::time::Duration::from_millis(500));
+            }
+            Box::new(EvdevSource::open(&input_devices, device_matcher, grab)?)
+        }
+        true => Box::new(StdinSource::new()?),
+    };
+
+    AltTabInterceptor::new(
+        source,
+        tx,
+        key_config,
+        Box::new(clock::MonotonicClock),
+        output,
+        grab,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn socket_path_is_asked_for_without_the_environment() {
+        let env = |vars: &'static [(&str, &str)]| {
+            move |var: &str| {
+                let value = vars.iter().find(|&&(name, _)| name == var)?.1;
+                Some(std::ffi::OsString::from(value))
+            }
+        };
+        let ask = |answers: &'static [&str]| {
+            move |wm: &str| -> Option<PathBuf> {
+                answers
+                    .contains(&wm)
+                    .then(|| format!("/run/{wm}.sock").into())
+            }
+        };
+        let find = |vars, answers| find_socket_path(env(vars), ask(answers));
+        let path = |path: &str| Some(PathBuf::from(path));
+
+        assert_eq!(find(&[("SWAYSOCK", "/tmp/s")], &["sway"]), path("/tmp/s"));
+        assert_eq!(
+            find(&[("I3SOCK", "/tmp/i"), ("SWAYSOCK", "/tmp/s")], &[]),
+            path("/tmp/i")
+        );
+        assert_eq!(find(&[("SWAYSOCK", "")], &["i3"]), path("/run/i3.sock"));
+        assert_eq!(find(&[], &["sway", "i3"]), path("/run/sway.sock"));
+        assert_eq!(find(&[], &[]), None);
+    }
+
+    #[test]
+    fn key_names_and_keycodes_are_parsed() {
+        // The keysyms are resolved with the host's keymap, they're tested in keysyms
+        assert_eq!(parse_keycode("15"), Ok(evdev_rs::enums::EV_KEY::KEY_TAB));
+        assert_eq!(parse_keycode("KEY_TAB"), parse_keycode("15"));
+        assert!(parse_keycode("KEY_SUPER").is_err());
+        assert!(parse_keycode("Super_X").is_err());
+
+        let chord = "KEY_LEFTMETA+KEY_LEFTALT,KEY_TAB".parse::<Chord>().unwrap();
+        assert_eq!(
+            chord.modifiers,
+            [
+                evdev_rs::enums::EV_KEY::KEY_LEFTMETA,
+                evdev_rs::enums::EV_KEY::KEY_LEFTALT
+            ]
+        );
+        assert_eq!(chord.trigger, evdev_rs::enums::EV_KEY::KEY_TAB);
+    }
+}
@@ -0,0 +1,114 @@
+// Outbound notifications about committed switches, e.g. for home automation
+
+pub trait Notifier: Send {
+    // Called after a switch to the workspace is committed, must not block for long
+    fn notify(&mut self, workspace: &str);
+
+    // Called when the selection stays at an end of the list with --boundary clamp-flash
+    fn boundary_reached(&mut self) {}
+}
+
+pub struct NoopNotifier;
+
+impl Notifier for NoopNotifier {
+    fn notify(&mut self, _workspace: &str) {}
+}
+
+// POSTs {"workspace": "<name>"} to a plain HTTP URL, there's no TLS support to avoid pulling
+// in an HTTP client with all its dependencies
+#[cfg(feature = "webhook")]
+#[derive(Clone, Debug)]
+pub struct WebhookNotifier {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+#[cfg(feature = "webhook")]
+impl std::str::FromStr for WebhookNotifier {
+    type Err = String;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or("only http:// URLs are supported")?;
+        let (authority, path) = match rest.find('/') {
+            Some(idx) => rest.split_at(idx),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse().map_err(|_| format!("invalid port: {port}"))?,
+            ),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err("the host is empty".to_string());
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+#[cfg(feature = "webhook")]
+impl WebhookNotifier {
+    const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+    fn post(host: &str, port: u16, path: &str, body: &str) -> std::io::Result<String> {
+        use std::io::{BufRead, Write};
+        use std::net::ToSocketAddrs;
+
+        let addr = (host, port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or(std::io::ErrorKind::NotFound)?;
+        let mut stream = std::net::TcpStream::connect_timeout(&addr, Self::TIMEOUT)?;
+        stream.set_read_timeout(Some(Self::TIMEOUT))?;
+        stream.set_write_timeout(Some(Self::TIMEOUT))?;
+        write!(
+            stream,
+            "POST {path} HTTP/1.1\r\nHost: {host}\r\nContent-Type: application/json\r\n\
+            Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )?;
+
+        let mut status = String::new();
+        std::io::BufReader::new(stream).read_line(&mut status)?;
+        Ok(status.trim_end().to_string())
+    }
+}
+
+#[cfg(feature = "webhook")]
+impl Notifier for WebhookNotifier {
+    fn notify(&mut self, workspace: &str) {
+        self.send(serde_json::json!({ "workspace": workspace }).to_string());
+    }
+
+    fn boundary_reached(&mut self) {
+        self.send(serde_json::json!({ "boundary": true }).to_string());
+    }
+}
+
+#[cfg(feature = "webhook")]
+impl WebhookNotifier {
+    fn send(&self, body: String) {
+        let (host, port, path) = (self.host.clone(), self.port, self.path.clone());
+        // Don't hold up the switcher while the request is in flight
+        std::thread::spawn(move || match Self::post(&host, port, &path, &body) {
+            Ok(status)
+                if status
+                    .split_whitespace()
+                    .nth(1)
+                    .is_some_and(|c| c.starts_with('2')) =>
+            {
+                log::debug!("Webhook responded with {status}");
+            }
+            Ok(status) => log::warn!("Webhook responded with \"{status}\""),
+            Err(e) => log::warn!("Can't send the webhook request: {e}"),
+        });
+    }
+}
@@ -0,0 +1,78 @@
+// Destinations for the events which aren't intercepted
+
+use std::os::fd::RawFd;
+
+use evdev_rs::{InputEvent, UInputDevice};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputBackend {
+    // A uinput device, i.e. a fake keyboard on the kernel level
+    Uinput,
+    // The compositor's virtual keyboard protocol, no uinput access is needed
+    Wayland,
+}
+
+// How the uinput device presents itself, by default it's a copy of the input device (or a
+// union of the input devices' capabilities). Sway's input rules match it by these.
+#[derive(Clone, Debug, Default)]
+pub struct UinputProperties {
+    pub name: Option<String>,
+    pub vendor: Option<u16>,
+    pub product: Option<u16>,
+    // Only the key events are supported, not the relative axes, LEDs etc.
+    pub keys_only: bool,
+}
+
+impl UinputProperties {
+    pub fn is_default(&self) -> bool {
+        self.name.is_none() && self.vendor.is_none() && self.product.is_none() && !self.keys_only
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct OutputConfig {
+    pub backend: OutputBackend,
+    pub uinput: UinputProperties,
+}
+
+pub trait EventSink: Send {
+    fn write_event(&mut self, ev: &InputEvent) -> std::io::Result<()>;
+
+    // Writes the events of a frame, ending with a SYN_REPORT, together
+    fn write_frame(&mut self, events: &[InputEvent]) -> std::io::Result<()> {
+        events.iter().try_for_each(|ev| self.write_event(ev))
+    }
+
+    // A human-readable description for the logs
+    fn describe(&self) -> String;
+}
+
+impl EventSink for UInputDevice {
+    fn write_event(&mut self, ev: &InputEvent) -> std::io::Result<()> {
+        UInputDevice::write_event(self, ev)
+    }
+
+    // A single write, with the timestamps of the source events (libevdev zeroes them). Recent
+    // kernels use the timestamps (they're monotonic, like the input devices are switched to),
+    // older ones and zero timestamps get the time of the write.
+    fn write_frame(&mut self, events: &[InputEvent]) -> std::io::Result<()> {
+        let fd: RawFd = self
+            .as_fd()
+            .ok_or_else(|| std::io::Error::other("the uinput device has no file descriptor"))?;
+        let raw = events.iter().map(InputEvent::as_raw).collect::<Vec<_>>();
+        let len = std::mem::size_of_val(raw.as_slice());
+        match unsafe { libc::write(fd, raw.as_ptr().cast(), len) } {
+            n if n < 0 => Err(std::io::Error::last_os_error()),
+            n if n as usize != len => Err(std::io::Error::other("short write")),
+            _ => Ok(()),
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "uinput device, devnode: {}, syspath: {}",
+            self.devnode().unwrap_or("none"),
+            self.syspath().unwrap_or("none")
+        )
+    }
+}
@@ -0,0 +1,152 @@
+use std::error::Error;
+use std::ffi::{CStr, CString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+
+// The group owning the input devices, kept so they can be opened again after a hotplug, an error
+// or the sleep
+const INPUT_GROUP: &CStr = c"input";
+
+struct Passwd {
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+    home: PathBuf,
+}
+
+fn lookup_user(name: &CString) -> Result<Passwd, Box<dyn Error>> {
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+
+    let err = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut pwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if err != 0 {
+        return Err(format!(
+            "can't look up user {name:?}: {}",
+            std::io::Error::from_raw_os_error(err)
+        )
+        .into());
+    }
+    if result.is_null() {
+        return Err(format!("no such user: {name:?}").into());
+    }
+    let home = unsafe { CStr::from_ptr(pwd.pw_dir) };
+    Ok(Passwd {
+        uid: pwd.pw_uid,
+        gid: pwd.pw_gid,
+        home: PathBuf::from(std::ffi::OsStr::from_bytes(home.to_bytes())),
+    })
+}
+
+fn lookup_group(name: &CStr) -> Result<Option<libc::gid_t>, Box<dyn Error>> {
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut result: *mut libc::group = std::ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+
+    let err = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut grp,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if err != 0 {
+        return Err(format!(
+            "can't look up group {name:?}: {}",
+            std::io::Error::from_raw_os_error(err)
+        )
+        .into());
+    }
+    Ok((!result.is_null()).then_some(grp.gr_gid))
+}
+
+// Adds the group to the supplementary groups set by initgroups
+fn add_group(gid: libc::gid_t) -> Result<(), Box<dyn Error>> {
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    let mut groups = vec![0; count.max(0) as usize];
+    let count = unsafe { libc::getgroups(groups.len() as libc::c_int, groups.as_mut_ptr()) };
+    if count < 0 {
+        let e = std::io::Error::last_os_error();
+        return Err(format!("can't get supplementary groups: {e}").into());
+    }
+    groups.truncate(count as usize);
+    if groups.contains(&gid) {
+        return Ok(());
+    }
+    groups.push(gid);
+    if unsafe { libc::setgroups(groups.len(), groups.as_ptr()) } != 0 {
+        let e = std::io::Error::last_os_error();
+        return Err(format!("can't set supplementary groups: {e}").into());
+    }
+    Ok(())
+}
+
+// The environment still describes root, the state and config files would end up in its home
+fn set_user_environment(user: &str, passwd: &Passwd) {
+    let home = &passwd.home;
+    std::env::set_var("HOME", home);
+    std::env::set_var("USER", user);
+    std::env::set_var("LOGNAME", user);
+    std::env::set_var("XDG_CONFIG_HOME", home.join(".config"));
+    std::env::set_var("XDG_DATA_HOME", home.join(".local/share"));
+    std::env::set_var("XDG_STATE_HOME", home.join(".local/state"));
+    std::env::set_var("XDG_CACHE_HOME", home.join(".cache"));
+    std::env::set_var("XDG_RUNTIME_DIR", format!("/run/user/{}", passwd.uid));
+}
+
+// Permanently switches the process from root to the given user, with the user's primary and
+// supplementary groups and the input group, and points the environment to the user's home.
+// Returns whether the input group has been kept, the input devices can't be opened again without
+// it. Must be called before spawning any threads.
+pub fn drop_privileges(user: &str) -> Result<bool, Box<dyn Error>> {
+    if unsafe { libc::geteuid() } != 0 {
+        return Err("dropping privileges with --user requires starting as root".into());
+    }
+
+    let name = CString::new(user).map_err(|_| "the user name contains a NUL byte")?;
+    let passwd = lookup_user(&name)?;
+    if passwd.uid == 0 {
+        return Err("--user must name an unprivileged user, not root".into());
+    }
+
+    // Groups go first, changing them is not permitted after giving up root
+    if unsafe { libc::initgroups(name.as_ptr(), passwd.gid) } != 0 {
+        return Err(format!(
+            "can't set supplementary groups: {}",
+            std::io::Error::last_os_error()
+        )
+        .into());
+    }
+    let input_group = lookup_group(INPUT_GROUP)?;
+    if let Some(gid) = input_group {
+        add_group(gid)?;
+    }
+    if unsafe { libc::setgid(passwd.gid) } != 0 {
+        return Err(format!("can't set group id: {}", std::io::Error::last_os_error()).into());
+    }
+    if unsafe { libc::setuid(passwd.uid) } != 0 {
+        return Err(format!("can't set user id: {}", std::io::Error::last_os_error()).into());
+    }
+
+    // Make sure there's no way back
+    if unsafe { libc::setuid(0) } == 0 {
+        return Err("privileges can be regained after dropping them".into());
+    }
+
+    log::info!(
+        "Dropped privileges to user {user} (uid = {}, gid = {})",
+        passwd.uid,
+        passwd.gid
+    );
+    set_user_environment(user, &passwd);
+    Ok(input_group.is_some())
+}
@@ -0,0 +1,34 @@
+// Scheduling priority of the interceptor threads. Every keystroke goes through them, so they
+// shouldn't wait behind other processes when the system is loaded. Real-time scheduling needs
+// CAP_SYS_NICE or an RLIMIT_RTPRIO limit allowing it, a raised niceness is tried otherwise.
+
+// The niceness used when real-time scheduling isn't allowed
+const FALLBACK_NICENESS: libc::c_int = -10;
+
+// Switches the calling thread to SCHED_FIFO with the given priority (1-99), or at least raises
+// its niceness. Failures are only logged, the interception works either way.
+pub fn raise_priority(priority: i32) {
+    let param = libc::sched_param {
+        sched_priority: priority,
+    };
+    // The processes spawned from the thread get the normal scheduling
+    let policy = libc::SCHED_FIFO | libc::SCHED_RESET_ON_FORK;
+    let err = unsafe { libc::pthread_setschedparam(libc::pthread_self(), policy, &param) };
+    if err == 0 {
+        log::debug!("Using real-time scheduling with priority {priority}");
+        return;
+    }
+    let rt_error = std::io::Error::from_raw_os_error(err);
+
+    // The niceness is per thread on Linux, set through the thread id
+    let tid = unsafe { libc::gettid() };
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, FALLBACK_NICENESS) } == 0 {
+        log::warn!(
+            "Can't use real-time scheduling ({rt_error}), raised the niceness to \
+            {FALLBACK_NICENESS} instead"
+        );
+    } else {
+        let e = std::io::Error::last_os_error();
+        log::warn!("Can't raise the interceptor's priority: {rt_error}, niceness: {e}");
+    }
+}
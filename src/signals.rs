@@ -0,0 +1,150 @@
+// Runtime control with signals: SIGUSR1 pauses the interception (the keyboard is ungrabbed, e.g.
+// to hand it over to a VM), SIGUSR2 resumes it, SIGHUP reloads the key configuration. The system
+// going to sleep and waking up (see the sleep module) is handled the same way. The requested
+// state is only recorded, and the interceptors are woken up through their pipes to act on it in
+// their own threads.
+
+use std::cell::Cell;
+use std::error::Error;
+use std::os::fd::RawFd;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, Ordering};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Request {
+    Pause,
+    Resume,
+    Suspend,
+    Wake,
+    Reload,
+}
+
+// Every interceptor thread has its own pipe, so the wakeup isn't consumed by just one of them
+const MAX_LISTENERS: usize = 32;
+
+static PAUSED: AtomicBool = AtomicBool::new(false);
+static SUSPENDED: AtomicBool = AtomicBool::new(false);
+// The number of reload requests so far
+static RELOADS: AtomicU32 = AtomicU32::new(0);
+static WAKE_WRITE_FDS: [AtomicI32; MAX_LISTENERS] = [const { AtomicI32::new(-1) }; MAX_LISTENERS];
+
+// The calling thread's pipe and the state it has acted on
+#[derive(Clone, Copy)]
+struct Listener {
+    fd: RawFd,
+    paused: bool,
+    suspended: bool,
+    reloads: u32,
+}
+
+thread_local! {
+    static LISTENER: Cell<Option<Listener>> = const { Cell::new(None) };
+}
+
+extern "C" fn handle_signal(signal: libc::c_int) {
+    match signal {
+        libc::SIGHUP => {
+            RELOADS.fetch_add(1, Ordering::SeqCst);
+        }
+        _ => PAUSED.store(signal == libc::SIGUSR1, Ordering::SeqCst),
+    }
+    wake_listeners();
+}
+
+// Only async-signal-safe calls here, a full pipe is fine since the state is stored
+fn wake_listeners() {
+    for fd in &WAKE_WRITE_FDS {
+        let fd = fd.load(Ordering::SeqCst);
+        if fd >= 0 {
+            unsafe { libc::write(fd, [0u8].as_ptr().cast(), 1) };
+        }
+    }
+}
+
+pub fn install() -> Result<(), Box<dyn Error>> {
+    for signal in [libc::SIGUSR1, libc::SIGUSR2, libc::SIGHUP] {
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        action.sa_sigaction = handle_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        if unsafe { libc::sigaction(signal, &action, std::ptr::null_mut()) } < 0 {
+            let e = std::io::Error::last_os_error();
+            return Err(format!("can't install the signal handler: {e}").into());
+        }
+    }
+    Ok(())
+}
+
+// Called from the sleep watcher when the system is about to sleep or has woken up
+pub fn set_suspended(suspended: bool) {
+    SUSPENDED.store(suspended, Ordering::SeqCst);
+    wake_listeners();
+}
+
+fn listener() -> Option<Listener> {
+    if let Some(listener) = LISTENER.get() {
+        return Some(listener);
+    }
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } < 0 {
+        let e = std::io::Error::last_os_error();
+        log::error!("Can't create the signal pipe, pausing won't work: {e}");
+        return None;
+    }
+    let registered = WAKE_WRITE_FDS.iter().any(|fd| {
+        fd.compare_exchange(-1, fds[1], Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    });
+    if !registered {
+        log::error!("Too many interceptors, pausing won't work for some of them");
+        unsafe { libc::close(fds[0]) };
+        unsafe { libc::close(fds[1]) };
+        return None;
+    }
+    // The reloads requested before aren't for this thread, it's just starting
+    let listener = Listener {
+        fd: fds[0],
+        paused: false,
+        suspended: false,
+        reloads: RELOADS.load(Ordering::SeqCst),
+    };
+    LISTENER.set(Some(listener));
+    Some(listener)
+}
+
+// Becomes readable when a request for the calling thread arrives, None if there's no pipe
+pub fn wake_fd() -> Option<RawFd> {
+    listener().map(|listener| listener.fd)
+}
+
+// Returns the next request the calling thread hasn't acted on yet, the sleep state first
+pub fn take_request() -> Option<Request> {
+    let mut listener = listener()?;
+    let mut buf = [0u8; 64];
+    while unsafe { libc::read(listener.fd, buf.as_mut_ptr().cast(), buf.len()) } > 0 {}
+
+    let (paused, suspended, reloads) = (
+        PAUSED.load(Ordering::SeqCst),
+        SUSPENDED.load(Ordering::SeqCst),
+        RELOADS.load(Ordering::SeqCst),
+    );
+    let request = if suspended != listener.suspended {
+        listener.suspended = suspended;
+        match suspended {
+            true => Request::Suspend,
+            false => Request::Wake,
+        }
+    } else if paused != listener.paused {
+        listener.paused = paused;
+        match paused {
+            true => Request::Pause,
+            false => Request::Resume,
+        }
+    } else if reloads != listener.reloads {
+        // Several requests in a row are handled with a single reload
+        listener.reloads = reloads;
+        Request::Reload
+    } else {
+        return None;
+    };
+    LISTENER.set(Some(listener));
+    Some(request)
+}
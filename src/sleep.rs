@@ -0,0 +1,244 @@
+// Releasing the keyboards across suspend: logind's PrepareForSleep signal is watched on the
+// system bus, and the interceptors close the input devices before the system sleeps and open
+// them again after it wakes up, since the grabbed devices may come back broken otherwise.
+// A delay inhibitor lock gives them the time to do it. libdbus is loaded at runtime like
+// libxkbcommon, without it (or logind) the devices are just kept open.
+
+use std::error::Error;
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::time::Duration;
+
+use crate::signals;
+
+const LIBRARY: &CStr = c"libdbus-1.so.3";
+const DBUS_BUS_SYSTEM: c_int = 1;
+const DBUS_TYPE_BOOLEAN: c_int = b'b' as c_int;
+const DBUS_TYPE_STRING: c_int = b's' as c_int;
+const DBUS_TYPE_UNIX_FD: c_int = b'h' as c_int;
+const MATCH_RULE: &CStr =
+    c"type='signal',interface='org.freedesktop.login1.Manager',member='PrepareForSleep'";
+// The time for the interceptors to close the devices before the sleep is allowed to proceed
+const RELEASE_DELAY: Duration = Duration::from_millis(200);
+
+// Opaque libdbus structures allocated by the caller, with room to spare
+type DBusError = [usize; 8];
+type DBusMessageIter = [usize; 16];
+
+struct DBus {
+    error_init: unsafe extern "C" fn(*mut DBusError),
+    error_free: unsafe extern "C" fn(*mut DBusError),
+    read_write: unsafe extern "C" fn(*mut c_void, c_int) -> u32,
+    pop_message: unsafe extern "C" fn(*mut c_void) -> *mut c_void,
+    send_with_reply_and_block:
+        unsafe extern "C" fn(*mut c_void, *mut c_void, c_int, *mut DBusError) -> *mut c_void,
+    new_method_call: unsafe extern "C" fn(
+        *const c_char,
+        *const c_char,
+        *const c_char,
+        *const c_char,
+    ) -> *mut c_void,
+    is_signal: unsafe extern "C" fn(*mut c_void, *const c_char, *const c_char) -> u32,
+    message_unref: unsafe extern "C" fn(*mut c_void),
+    iter_init: unsafe extern "C" fn(*mut c_void, *mut DBusMessageIter) -> u32,
+    iter_init_append: unsafe extern "C" fn(*mut c_void, *mut DBusMessageIter),
+    iter_append_basic: unsafe extern "C" fn(*mut DBusMessageIter, c_int, *const c_void) -> u32,
+    iter_get_arg_type: unsafe extern "C" fn(*mut DBusMessageIter) -> c_int,
+    iter_get_basic: unsafe extern "C" fn(*mut DBusMessageIter, *mut c_void),
+}
+
+struct SleepWatcher {
+    dbus: DBus,
+    connection: *mut c_void,
+    // The delay inhibitor lock, released when the system may go to sleep
+    lock_fd: Option<c_int>,
+}
+
+// The connection is only used by the watcher's thread after it's set up
+unsafe impl Send for SleepWatcher {}
+
+// Subscribes to the sleep signal and watches it in a thread
+pub fn watch() -> Result<(), Box<dyn Error>> {
+    let mut watcher = unsafe { SleepWatcher::connect()? };
+    watcher.take_lock();
+    std::thread::Builder::new()
+        .name("sleep watcher".to_string())
+        .spawn(move || watcher.run())
+        .map_err(|e| format!("can't start the sleep watcher thread: {e}"))?;
+    Ok(())
+}
+
+impl SleepWatcher {
+    unsafe fn connect() -> Result<Self, String> {
+        let lib = libc::dlopen(LIBRARY.as_ptr(), libc::RTLD_NOW);
+        if lib.is_null() {
+            return Err(format!("can't load {}", LIBRARY.to_string_lossy()));
+        }
+
+        let bus_get: unsafe extern "C" fn(c_int, *mut DBusError) -> *mut c_void =
+            symbol(lib, c"dbus_bus_get")?;
+        let add_match: unsafe extern "C" fn(*mut c_void, *const c_char, *mut DBusError) =
+            symbol(lib, c"dbus_bus_add_match")?;
+        let set_exit_on_disconnect: unsafe extern "C" fn(*mut c_void, u32) =
+            symbol(lib, c"dbus_connection_set_exit_on_disconnect")?;
+        let dbus = DBus {
+            error_init: symbol(lib, c"dbus_error_init")?,
+            error_free: symbol(lib, c"dbus_error_free")?,
+            read_write: symbol(lib, c"dbus_connection_read_write")?,
+            pop_message: symbol(lib, c"dbus_connection_pop_message")?,
+            send_with_reply_and_block: symbol(lib, c"dbus_connection_send_with_reply_and_block")?,
+            new_method_call: symbol(lib, c"dbus_message_new_method_call")?,
+            is_signal: symbol(lib, c"dbus_message_is_signal")?,
+            message_unref: symbol(lib, c"dbus_message_unref")?,
+            iter_init: symbol(lib, c"dbus_message_iter_init")?,
+            iter_init_append: symbol(lib, c"dbus_message_iter_init_append")?,
+            iter_append_basic: symbol(lib, c"dbus_message_iter_append_basic")?,
+            iter_get_arg_type: symbol(lib, c"dbus_message_iter_get_arg_type")?,
+            iter_get_basic: symbol(lib, c"dbus_message_iter_get_basic")?,
+        };
+
+        let mut error = [0; 8];
+        (dbus.error_init)(&mut error);
+        let connection = bus_get(DBUS_BUS_SYSTEM, &mut error);
+        if connection.is_null() {
+            let e = dbus.take_error(&mut error);
+            return Err(format!("can't connect to the system bus: {e}"));
+        }
+        // libdbus exits the process when the bus goes away, unless told not to
+        set_exit_on_disconnect(connection, 0);
+        add_match(connection, MATCH_RULE.as_ptr(), &mut error);
+        if error[0] != 0 {
+            let e = dbus.take_error(&mut error);
+            return Err(format!("can't subscribe to the sleep signal: {e}"));
+        }
+        Ok(Self {
+            dbus,
+            connection,
+            lock_fd: None,
+        })
+    }
+
+    fn run(&mut self) {
+        loop {
+            if unsafe { (self.dbus.read_write)(self.connection, -1) } == 0 {
+                log::warn!("The system bus connection is closed, suspend won't be handled");
+                return;
+            }
+            loop {
+                let message = unsafe { (self.dbus.pop_message)(self.connection) };
+                if message.is_null() {
+                    break;
+                }
+                if let Some(sleeping) = unsafe { self.prepare_for_sleep(message) } {
+                    self.on_prepare_for_sleep(sleeping);
+                }
+                unsafe { (self.dbus.message_unref)(message) };
+            }
+        }
+    }
+
+    fn on_prepare_for_sleep(&mut self, sleeping: bool) {
+        signals::set_suspended(sleeping);
+        if sleeping {
+            log::info!("The system is going to sleep, releasing the keyboards");
+            std::thread::sleep(RELEASE_DELAY);
+            if let Some(fd) = self.lock_fd.take() {
+                unsafe { libc::close(fd) };
+            }
+        } else {
+            log::info!("The system has woken up, taking the keyboards back");
+            self.take_lock();
+        }
+    }
+
+    // The argument of the PrepareForSleep signal, None for other messages
+    unsafe fn prepare_for_sleep(&self, message: *mut c_void) -> Option<bool> {
+        let interface = c"org.freedesktop.login1.Manager";
+        if (self.dbus.is_signal)(message, interface.as_ptr(), c"PrepareForSleep".as_ptr()) == 0 {
+            return None;
+        }
+        let mut iter = [0; 16];
+        if (self.dbus.iter_init)(message, &mut iter) == 0
+            || (self.dbus.iter_get_arg_type)(&mut iter) != DBUS_TYPE_BOOLEAN
+        {
+            return None;
+        }
+        let mut value: u32 = 0;
+        (self.dbus.iter_get_basic)(&mut iter, (&mut value as *mut u32).cast());
+        Some(value != 0)
+    }
+
+    fn take_lock(&mut self) {
+        match unsafe { self.inhibit() } {
+            Ok(fd) => self.lock_fd = Some(fd),
+            Err(e) => log::warn!("Can't delay the sleep until the keyboards are released: {e}"),
+        }
+    }
+
+    // Takes a delay inhibitor lock for sleep, which lasts until the returned fd is closed
+    unsafe fn inhibit(&self) -> Result<c_int, String> {
+        let message = (self.dbus.new_method_call)(
+            c"org.freedesktop.login1".as_ptr(),
+            c"/org/freedesktop/login1".as_ptr(),
+            c"org.freedesktop.login1.Manager".as_ptr(),
+            c"Inhibit".as_ptr(),
+        );
+        if message.is_null() {
+            return Err("out of memory".to_string());
+        }
+        let mut iter = [0; 16];
+        (self.dbus.iter_init_append)(message, &mut iter);
+        let args = [
+            c"sleep",
+            c"swaywm-alttab",
+            c"Releasing the keyboards",
+            c"delay",
+        ];
+        for arg in args {
+            let ptr = arg.as_ptr();
+            let value = (&ptr as *const *const c_char).cast();
+            (self.dbus.iter_append_basic)(&mut iter, DBUS_TYPE_STRING, value);
+        }
+
+        let mut error = [0; 8];
+        (self.dbus.error_init)(&mut error);
+        let reply = (self.dbus.send_with_reply_and_block)(self.connection, message, -1, &mut error);
+        (self.dbus.message_unref)(message);
+        if reply.is_null() {
+            return Err(self.dbus.take_error(&mut error));
+        }
+        let mut fd = -1;
+        if (self.dbus.iter_init)(reply, &mut iter) != 0
+            && (self.dbus.iter_get_arg_type)(&mut iter) == DBUS_TYPE_UNIX_FD
+        {
+            (self.dbus.iter_get_basic)(&mut iter, (&mut fd as *mut c_int).cast());
+        }
+        (self.dbus.message_unref)(reply);
+        match fd {
+            -1 => Err("no lock in the reply".to_string()),
+            fd => Ok(fd),
+        }
+    }
+}
+
+// The function from the library, as a function pointer of type F
+unsafe fn symbol<F>(lib: *mut c_void, name: &CStr) -> Result<F, String> {
+    let sym = libc::dlsym(lib, name.as_ptr());
+    match sym.is_null() {
+        true => Err(format!("no {} in libdbus", name.to_string_lossy())),
+        false => Ok(std::mem::transmute_copy::<*mut c_void, F>(&sym)),
+    }
+}
+
+impl DBus {
+    // The message of the set error, which is cleared
+    unsafe fn take_error(&self, error: &mut DBusError) -> String {
+        // The fields are the name and the message strings, then the private ones
+        let message = error[1] as *const c_char;
+        let text = match message.is_null() {
+            true => "unknown error".to_string(),
+            false => CStr::from_ptr(message).to_string_lossy().into_owned(),
+        };
+        (self.error_free)(error);
+        text
+    }
+}
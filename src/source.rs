@@ -0,0 +1,672 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::BufRead;
+use std::os::fd::{AsFd, AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use evdev_rs::enums::{EventCode, EventType, EV_KEY, EV_MSC, EV_SYN};
+use evdev_rs::{
+    Device, DeviceWrapper, EventCodeIterator, InputEvent, ReadFlag, ReadStatus, TimeVal,
+    UInputDevice, UninitDevice,
+};
+
+use crate::devices::{DeviceMatcher, DeviceWatcher};
+use crate::output::{EventSink, UinputProperties};
+use crate::signals;
+
+// How often the disappeared devices are tried to be opened again, in case the hotplug
+// notification doesn't come (e.g. without inotify, or when the device comes back under
+// the same node)
+const REOPEN_INTERVAL: Duration = Duration::from_secs(2);
+
+// A source of input events for the keypress interceptor. Key events are normalized to
+// evdev's InputEvent, so the interceptor state machine doesn't care where they come from.
+pub trait EventSource: Send {
+    // Blocks until the next event is available or the timeout expires (returns None then),
+    // no timeout means waiting indefinitely
+    fn next_event(&mut self, timeout: Option<Duration>) -> std::io::Result<Option<InputEvent>>;
+
+    // Creates a device to forward the non-intercepted events to, None means the
+    // events are dropped
+    fn create_output_device(
+        &mut self,
+        properties: &UinputProperties,
+    ) -> std::io::Result<Option<Box<dyn EventSink>>>;
+
+    // Whether the key is currently held down according to the source, None if it can't tell
+    fn key_state(&self, key: EV_KEY) -> Option<bool>;
+
+    // Releases the input devices to the rest of the system (or takes them back), the events
+    // are discarded meanwhile. After resuming, a SYN_DROPPED is returned, since the key state
+    // has changed without the events being seen.
+    fn set_paused(&mut self, paused: bool) -> std::io::Result<()>;
+
+    // Takes the input devices away from the rest of the system or gives them back, only with
+    // GrabPolicy::Lazy. The events keep coming either way.
+    fn set_grabbed(&mut self, grabbed: bool) -> std::io::Result<()>;
+
+    // Closes the input devices before the system goes to sleep, and opens them again after it
+    // wakes up
+    fn set_suspended(&mut self, suspended: bool);
+}
+
+// When the input devices are grabbed, i.e. their events only reach us
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GrabPolicy {
+    Always,
+    // Only while asked to with set_grabbed()
+    Lazy,
+    // The devices are only watched (opened read-only)
+    Never,
+    // Watched like with Never, but the intercepted events take effect, while the devices'
+    // events keep reaching the rest of the system too (e.g. for game controllers)
+    Shared,
+}
+
+// Waits for one of the file descriptors to become readable, returns its index or None on
+// timeout
+fn poll_readable(fds: &[RawFd], timeout: Option<Duration>) -> Option<usize> {
+    let mut pollfds = fds
+        .iter()
+        .map(|&fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect::<Vec<_>>();
+    let timeout_ms = timeout.map_or(-1, |t| t.as_millis().try_into().unwrap_or(libc::c_int::MAX));
+    let result = unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as _, timeout_ms) };
+    match result {
+        0 => None,
+        // An error (e.g. EINTR) is treated as readiness, the following read will report real
+        // problems (or would block, which is handled too)
+        r if r < 0 => Some(0),
+        _ => pollfds.iter().position(|pollfd| pollfd.revents != 0),
+    }
+}
+
+// Real keyboards, grabbed exclusively so their events reach sway only through us
+pub struct EvdevSource {
+    devices: Vec<(PathBuf, Device)>,
+    // The device the last event has been read from, it's preferred until its frame is over,
+    // so frames from different devices don't get mixed
+    current: usize,
+    // Devices plugged in later are grabbed if they match any of these
+    hotplug_matchers: Vec<DeviceMatcher>,
+    watcher: Option<DeviceWatcher>,
+    // Our own uinput device, which shouldn't be grabbed even if it looks like a keyboard we want
+    output_devnode: Option<PathBuf>,
+    // Events bringing the state up to date after a SYN_DROPPED, returned before any new ones
+    sync_events: VecDeque<InputEvent>,
+    paused: bool,
+    policy: GrabPolicy,
+    // Whether the devices are grabbed (or are going to be after the pause)
+    grabbed: bool,
+    // Keys held down on each device when it has been grabbed lazily, their releases are seen
+    // only by us, so the rest of the system has to be told about them after ungrabbing
+    held_before_grab: Vec<(PathBuf, Vec<EV_KEY>)>,
+    // The devices closed for the sleep, to be opened again after it
+    suspended_paths: Vec<PathBuf>,
+    // The paths of the devices which have disappeared, and when to try them again
+    lost_paths: Vec<PathBuf>,
+    next_reopen: Instant,
+}
+
+impl EvdevSource {
+    // The devices plugged in later are picked up if they match the matcher (when it's not
+    // empty) or look like one of the initially opened devices not matching it (e.g. a
+    // replugged keyboard given by path, or a pointer device)
+    pub fn open(
+        paths: &[PathBuf],
+        matcher: &DeviceMatcher,
+        policy: GrabPolicy,
+    ) -> Result<Self, Box<dyn Error>> {
+        let grabbed = policy == GrabPolicy::Always;
+        let devices = paths
+            .iter()
+            .map(|path| Ok((path.clone(), Self::open_device(path, policy, grabbed)?)))
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+        let mut hotplug_matchers = devices
+            .iter()
+            .filter(|(path, dev)| matcher.is_empty() || !matcher.matches(path, dev))
+            .map(|(_, dev)| DeviceMatcher::identity_of(dev))
+            .collect::<Vec<_>>();
+        if !matcher.is_empty() {
+            hotplug_matchers.push(matcher.clone());
+        }
+        let watcher = DeviceWatcher::new()
+            .inspect_err(|e| log::warn!("Keyboard hotplug won't work: {e}"))
+            .ok();
+
+        Ok(Self {
+            devices,
+            current: 0,
+            hotplug_matchers,
+            watcher,
+            output_devnode: None,
+            sync_events: VecDeque::new(),
+            paused: false,
+            policy,
+            grabbed,
+            held_before_grab: Vec::new(),
+            suspended_paths: Vec::new(),
+            lost_paths: Vec::new(),
+            next_reopen: Instant::now(),
+        })
+    }
+
+    fn open_device(path: &Path, policy: GrabPolicy, grab: bool) -> Result<Device, Box<dyn Error>> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(matches!(policy, GrabPolicy::Always | GrabPolicy::Lazy))
+            .open(path)
+            .map_err(|e| {
+                format!(
+                    "can't open keyboard input device file ({}): {e}",
+                    path.display()
+                )
+            })?;
+
+        let mut device = Device::new_from_file(file)
+            .map_err(|e| format!("can't create libevdev input device: {e}"))?;
+        if grab {
+            device
+                .grab(evdev_rs::GrabMode::Grab)
+                .map_err(|e| format!("can't grab the input device ({}): {e}", path.display()))?;
+        }
+        // Realtime timestamps (the default) jump with NTP adjustments and suspend. The timing
+        // in the interceptor uses Instant, which is monotonic anyway, so failing is fine.
+        if let Err(e) = device.set_clock_id(libc::CLOCK_MONOTONIC) {
+            log::warn!("Can't switch the input device to monotonic timestamps: {e}");
+        }
+
+        log::debug!("Keyboard input device: {}", path.display());
+        Ok(device)
+    }
+
+    // Grabs the newly appeared devices which match the configuration
+    fn add_plugged_devices(&mut self) {
+        let Some(watcher) = &self.watcher else {
+            return;
+        };
+        for path in watcher.changed_paths() {
+            let known = self.devices.iter().any(|(p, _)| *p == path);
+            if known || self.output_devnode.as_ref() == Some(&path) {
+                continue;
+            }
+            // Not being able to open it may be temporary, until udev sets up the permissions
+            let device = match Device::new_from_path(&path) {
+                Ok(device) => device,
+                Err(e) => {
+                    log::debug!("Can't open plugged in {}: {e}", path.display());
+                    continue;
+                }
+            };
+            if !self
+                .hotplug_matchers
+                .iter()
+                .any(|m| m.matches(&path, &device))
+            {
+                continue;
+            }
+            drop(device);
+            match Self::open_device(&path, self.policy, self.grabbed && !self.paused) {
+                Ok(device) => {
+                    log::info!("Keyboard {} is plugged in", path.display());
+                    // It may be a lost one under another path (e.g. a by-id symlink)
+                    self.lost_paths
+                        .retain(|lost| std::fs::canonicalize(lost).ok().as_ref() != Some(&path));
+                    self.devices.push((path, device));
+                }
+                Err(e) => log::warn!("Can't use plugged in keyboard: {e}"),
+            }
+        }
+    }
+
+    // Opens the disappeared devices which are back under their old paths
+    fn reopen_lost_devices(&mut self) {
+        self.next_reopen = Instant::now() + REOPEN_INTERVAL;
+        let open_paths = self
+            .devices
+            .iter()
+            .filter_map(|(path, _)| std::fs::canonicalize(path).ok())
+            .collect::<Vec<_>>();
+        let grab = self.grabbed && !self.paused;
+        let policy = self.policy;
+        let mut reopened = Vec::new();
+        self.lost_paths.retain(|path| {
+            let Ok(real_path) = std::fs::canonicalize(path) else {
+                return true;
+            };
+            // Picked up as plugged in already
+            if open_paths.contains(&real_path) {
+                return false;
+            }
+            match Self::open_device(path, policy, grab) {
+                Ok(device) => {
+                    log::info!("Keyboard {} is back", path.display());
+                    reopened.push((path.clone(), device));
+                    false
+                }
+                Err(e) => {
+                    log::debug!("Can't reopen the keyboard yet: {e}");
+                    true
+                }
+            }
+        });
+        self.devices.extend(reopened);
+    }
+
+    fn keys_down(device: &Device) -> Vec<EV_KEY> {
+        EventCodeIterator::new(&EventType::EV_KEY)
+            .filter(|code| device.event_value(code).is_some_and(|value| value != 0))
+            .filter_map(|code| match code {
+                EventCode::EV_KEY(key) => Some(key),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Writes the releases of the keys which aren't held anymore to the device itself, the
+    // kernel passes them on to everyone reading it (including us, they're no-ops then)
+    fn release_on_device(device: &Device, keys: &[EV_KEY]) -> std::io::Result<()> {
+        let time = TimeVal::new(0, 0);
+        let mut events = keys
+            .iter()
+            .filter(|&&key| device.event_value(&EventCode::EV_KEY(key)) == Some(0))
+            .map(|&key| InputEvent::new(&time, &EventCode::EV_KEY(key), 0).as_raw())
+            .collect::<Vec<_>>();
+        if events.is_empty() {
+            return Ok(());
+        }
+        let syn = EventCode::EV_SYN(EV_SYN::SYN_REPORT);
+        events.push(InputEvent::new(&time, &syn, 0).as_raw());
+        let len = std::mem::size_of_val(events.as_slice());
+        let fd = device.file().as_raw_fd();
+        match unsafe { libc::write(fd, events.as_ptr().cast(), len) } {
+            n if n < 0 => Err(std::io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    // libevdev has re-queried the device state after the kernel dropped events, and now
+    // provides the changes since the last event read as a series of events
+    fn read_sync_events(&mut self, idx: usize) {
+        loop {
+            match self.devices[idx].1.next_event(ReadFlag::SYNC) {
+                Ok((_, ev)) => self.sync_events.push_back(ev),
+                // The state is in sync
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    log::warn!("Can't resynchronize the input device state: {e}");
+                    return;
+                }
+            }
+        }
+    }
+
+    fn remove_device(&mut self, idx: usize) {
+        let (path, _) = self.devices.remove(idx);
+        log::info!("Keyboard {} is unplugged", path.display());
+        self.lost_paths.push(path);
+        self.next_reopen = Instant::now() + REOPEN_INTERVAL;
+        self.current = 0;
+        if self.devices.is_empty() {
+            log::warn!("All keyboards are unplugged, waiting for them to come back");
+        }
+    }
+
+    // Picks a device to read from: one with events already read by libevdev (the current
+    // one first), or one which becomes readable within the timeout. A signal request
+    // interrupts the wait.
+    fn ready_device(&mut self, timeout: Option<Duration>) -> std::io::Result<Option<usize>> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        loop {
+            let current = self.devices.get(self.current);
+            if current.is_some_and(|(_, dev)| dev.has_event_pending()) {
+                return Ok(Some(self.current));
+            }
+            if let Some(idx) = self
+                .devices
+                .iter()
+                .position(|(_, dev)| dev.has_event_pending())
+            {
+                return Ok(Some(idx));
+            }
+
+            let mut fds = self
+                .devices
+                .iter()
+                .map(|(_, dev)| dev.file().as_raw_fd())
+                .collect::<Vec<_>>();
+            let wake_fd = signals::wake_fd();
+            fds.extend(wake_fd);
+            fds.extend(self.watcher.as_ref().map(DeviceWatcher::fd));
+            let mut poll_deadline = deadline;
+            if !self.lost_paths.is_empty() {
+                poll_deadline =
+                    Some(deadline.map_or(self.next_reopen, |d| d.min(self.next_reopen)));
+            }
+            let timeout = poll_deadline.map(|d| d.saturating_duration_since(Instant::now()));
+            let Some(idx) = poll_readable(&fds, timeout) else {
+                if !self.lost_paths.is_empty() && Instant::now() >= self.next_reopen {
+                    self.reopen_lost_devices();
+                }
+                if deadline.is_some_and(|d| Instant::now() >= d) {
+                    return Ok(None);
+                }
+                continue;
+            };
+            match idx.checked_sub(self.devices.len()) {
+                None => return Ok(Some(idx)),
+                Some(0) if wake_fd.is_some() => return Err(std::io::ErrorKind::Interrupted.into()),
+                Some(_) => self.add_plugged_devices(),
+            }
+        }
+    }
+}
+
+impl EventSource for EvdevSource {
+    fn next_event(&mut self, timeout: Option<Duration>) -> std::io::Result<Option<InputEvent>> {
+        if let Some(ev) = self.sync_events.pop_front() {
+            return Ok(Some(ev));
+        }
+
+        loop {
+            let Some(idx) = self.ready_device(timeout)? else {
+                return Ok(None);
+            };
+            self.current = idx;
+
+            match self.devices[idx].1.next_event(ReadFlag::NORMAL) {
+                // The events are still read while paused, to keep libevdev's key state current
+                Ok((ReadStatus::Success, _)) if self.paused => {}
+                Ok((ReadStatus::Success, ev)) => return Ok(Some(ev)),
+                Ok((ReadStatus::Sync, _)) if self.paused => {
+                    self.read_sync_events(idx);
+                    self.sync_events.clear();
+                }
+                // The SYN_DROPPED event itself, passed on so the state derived from the events
+                // can be fixed up
+                Ok((ReadStatus::Sync, ev)) => {
+                    log::warn!("Input events have been dropped, resynchronizing the state");
+                    self.read_sync_events(idx);
+                    return Ok(Some(ev));
+                }
+                // Spurious wakeup, poll again
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                // The device is gone, or broken until it's opened again (e.g. after a resume)
+                Err(e) if matches!(e.raw_os_error(), Some(libc::ENODEV | libc::EIO)) => {
+                    self.remove_device(idx)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // The events of all devices go to a single uinput device, which has to be able to emit
+    // any of them
+    fn create_output_device(
+        &mut self,
+        properties: &UinputProperties,
+    ) -> std::io::Result<Option<Box<dyn EventSink>>> {
+        let output = match self.devices.as_slice() {
+            [(_, device)] if properties.is_default() => UInputDevice::create_from_device(device)?,
+            devices => {
+                let union = UninitDevice::new()
+                    .ok_or_else(|| std::io::Error::other("can't create a libevdev device"))?;
+                // A single device keeps its identity, unless it's overridden
+                let single = match devices {
+                    [(_, device)] => Some(device),
+                    _ => None,
+                };
+                let name = properties
+                    .name
+                    .as_deref()
+                    .or_else(|| single.and_then(|device| device.name()))
+                    .unwrap_or("swaywm-alttab keyboards");
+                union.set_name(name);
+                if let Some(vendor) = properties.vendor.or(single.map(|d| d.vendor_id())) {
+                    union.set_vendor_id(vendor);
+                }
+                if let Some(product) = properties.product.or(single.map(|d| d.product_id())) {
+                    union.set_product_id(product);
+                }
+                let ev_types = match properties.keys_only {
+                    true => &[EventType::EV_KEY][..],
+                    false => &[
+                        EventType::EV_KEY,
+                        EventType::EV_REL,
+                        EventType::EV_MSC,
+                        EventType::EV_LED,
+                    ],
+                };
+                for ev_type in ev_types {
+                    for code in EventCodeIterator::new(ev_type) {
+                        if devices.iter().any(|(_, dev)| dev.has_event_code(&code)) {
+                            union.enable_event_code(&code, None)?;
+                        }
+                    }
+                }
+                UInputDevice::create_from_device(&union)?
+            }
+        };
+        self.output_devnode = output.devnode().map(PathBuf::from);
+        Ok(Some(Box::new(output)))
+    }
+
+    fn set_paused(&mut self, paused: bool) -> std::io::Result<()> {
+        for (_, device) in self.devices.iter_mut().filter(|_| self.grabbed) {
+            device.grab(match paused {
+                true => evdev_rs::GrabMode::Ungrab,
+                false => evdev_rs::GrabMode::Grab,
+            })?;
+        }
+        self.paused = paused;
+        self.sync_events.clear();
+        if !paused {
+            let dropped = EventCode::EV_SYN(EV_SYN::SYN_DROPPED);
+            self.sync_events
+                .push_back(InputEvent::new(&TimeVal::new(0, 0), &dropped, 0));
+        }
+        Ok(())
+    }
+
+    fn set_grabbed(&mut self, grabbed: bool) -> std::io::Result<()> {
+        if self.policy != GrabPolicy::Lazy || grabbed == self.grabbed {
+            return Ok(());
+        }
+        if grabbed {
+            self.held_before_grab = self
+                .devices
+                .iter()
+                .map(|(path, device)| (path.clone(), Self::keys_down(device)))
+                .collect();
+        }
+        for (_, device) in self.devices.iter_mut().filter(|_| !self.paused) {
+            device.grab(match grabbed {
+                true => evdev_rs::GrabMode::Grab,
+                false => evdev_rs::GrabMode::Ungrab,
+            })?;
+        }
+        self.grabbed = grabbed;
+        if !grabbed {
+            for (path, held) in std::mem::take(&mut self.held_before_grab) {
+                if let Some((_, device)) = self.devices.iter().find(|(p, _)| *p == path) {
+                    Self::release_on_device(device, &held)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn set_suspended(&mut self, suspended: bool) {
+        if suspended {
+            // Closing them releases the grabs, and they may not survive the sleep anyway
+            self.suspended_paths = self.devices.drain(..).map(|(path, _)| path).collect();
+            self.current = 0;
+            self.sync_events.clear();
+            self.held_before_grab.clear();
+            return;
+        }
+        for path in std::mem::take(&mut self.suspended_paths) {
+            match Self::open_device(&path, self.policy, self.grabbed && !self.paused) {
+                Ok(device) => self.devices.push((path, device)),
+                Err(e) => {
+                    log::warn!("Can't reopen the keyboard after the sleep, retrying: {e}");
+                    self.lost_paths.push(path);
+                    self.next_reopen = Instant::now() + REOPEN_INTERVAL;
+                }
+            }
+        }
+        // The keys may have changed while the devices were closed
+        let dropped = EventCode::EV_SYN(EV_SYN::SYN_DROPPED);
+        self.sync_events
+            .push_back(InputEvent::new(&TimeVal::new(0, 0), &dropped, 0));
+    }
+
+    fn key_state(&self, key: EV_KEY) -> Option<bool> {
+        let code = EventCode::EV_KEY(key);
+        Some(
+            self.devices
+                .iter()
+                .any(|(_, dev)| dev.event_value(&code).is_some_and(|value| value != 0)),
+        )
+    }
+}
+
+// Key events written as text lines to stdin, for trying out a key configuration without
+// grabbing a real keyboard. Each line is a key name and a value (0 - release, 1 - press,
+// 2 - hold), e.g. "KEY_LEFTMETA 1". Nothing is forwarded anywhere.
+pub struct StdinSource {
+    stdin: std::io::BufReader<std::fs::File>,
+    // A key event is followed by a SYN_REPORT, like a real device does
+    pending_syn: bool,
+}
+
+impl StdinSource {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        // Bypass the stdin's own buffer, we need to know whether there's buffered input
+        let stdin = std::io::stdin()
+            .as_fd()
+            .try_clone_to_owned()
+            .map_err(|e| format!("can't duplicate the stdin file descriptor: {e}"))?;
+
+        log::debug!("Reading key events from stdin");
+        Ok(Self {
+            stdin: std::io::BufReader::new(stdin.into()),
+            pending_syn: false,
+        })
+    }
+
+    fn now() -> TimeVal {
+        TimeVal::try_from(std::time::SystemTime::now()).unwrap_or(TimeVal::new(0, 0))
+    }
+
+    fn parse_line(line: &str) -> Result<InputEvent, String> {
+        let mut words = line.split_whitespace();
+        let (Some(key), Some(value), None) = (words.next(), words.next(), words.next()) else {
+            return Err("expected a key name and a value".to_string());
+        };
+        // Relative axes (e.g. the wheel) take any value
+        if let Ok(axis) = key.parse::<evdev_rs::enums::EV_REL>() {
+            let value = value
+                .parse()
+                .map_err(|e| format!("invalid axis value {value}: {e}"))?;
+            return Ok(InputEvent::new(
+                &Self::now(),
+                &EventCode::EV_REL(axis),
+                value,
+            ));
+        }
+        // The scancode of the following key event, as a keyboard sends it
+        if key == "MSC_SCAN" {
+            let value = match value.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => value.parse(),
+            }
+            .map_err(|e| format!("invalid scancode {value}: {e}"))?;
+            return Ok(InputEvent::new(
+                &Self::now(),
+                &EventCode::EV_MSC(EV_MSC::MSC_SCAN),
+                value as i32,
+            ));
+        }
+        let key = crate::parse_keycode(key)?;
+        let value = match value.parse() {
+            Ok(value @ 0..=2) => value,
+            _ => return Err(format!("invalid key value: {value}")),
+        };
+        Ok(InputEvent::new(
+            &Self::now(),
+            &EventCode::EV_KEY(key),
+            value,
+        ))
+    }
+}
+
+impl EventSource for StdinSource {
+    fn next_event(&mut self, timeout: Option<Duration>) -> std::io::Result<Option<InputEvent>> {
+        if self.pending_syn {
+            self.pending_syn = false;
+            let syn = EventCode::EV_SYN(EV_SYN::SYN_REPORT);
+            return Ok(Some(InputEvent::new(&Self::now(), &syn, 0)));
+        }
+
+        loop {
+            // Signal requests interrupt the wait like with the devices
+            if self.stdin.buffer().is_empty() {
+                let wake_fd = signals::wake_fd();
+                let fds = [self.stdin.get_ref().as_raw_fd()]
+                    .into_iter()
+                    .chain(wake_fd)
+                    .collect::<Vec<_>>();
+                match poll_readable(&fds, timeout) {
+                    None => return Ok(None),
+                    Some(1) => return Err(std::io::ErrorKind::Interrupted.into()),
+                    Some(_) => {}
+                }
+            }
+
+            let mut line = String::new();
+            if self.stdin.read_line(&mut line)? == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match Self::parse_line(line) {
+                Ok(ev) => {
+                    // The scancode is in the same frame as the key event after it
+                    self.pending_syn = ev.event_code != EventCode::EV_MSC(EV_MSC::MSC_SCAN);
+                    return Ok(Some(ev));
+                }
+                Err(e) => log::warn!("Ignoring stdin line \"{line}\": {e}"),
+            }
+        }
+    }
+
+    fn create_output_device(
+        &mut self,
+        _properties: &UinputProperties,
+    ) -> std::io::Result<Option<Box<dyn EventSink>>> {
+        Ok(None)
+    }
+
+    fn key_state(&self, _key: EV_KEY) -> Option<bool> {
+        None
+    }
+
+    // Nothing is grabbed
+    fn set_paused(&mut self, _paused: bool) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn set_grabbed(&mut self, _grabbed: bool) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn set_suspended(&mut self, _suspended: bool) {}
+}
@@ -0,0 +1,62 @@
+// Keeping the most recently used order across restarts: the workspace names (the ids are new
+// each time sway creates a workspace) are written to a JSON file whenever the order changes
+// outside of a sequence, and the list is arranged by them at startup.
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+// $XDG_STATE_HOME/sway-alttab/state.json, with the default of ~/.local/state
+pub fn default_path() -> Option<PathBuf> {
+    let state_home = std::env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))?;
+    Some(state_home.join("sway-alttab").join("state.json"))
+}
+
+pub fn load(path: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("can't read the state file ({}): {e}", path.display()))?;
+    serde_json::from_str(&text)
+        .map_err(|e| format!("invalid state file ({}): {e}", path.display()).into())
+}
+
+// The file is replaced at once, so a crash in the middle doesn't leave half of it
+pub fn save(path: &Path, names: &[String]) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("can't create the state directory ({}): {e}", dir.display()))?;
+    }
+    let text = serde_json::to_string(names)?;
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, text)
+        .and_then(|_| std::fs::rename(&tmp_path, path))
+        .map_err(|e| format!("can't write the state file ({}): {e}", path.display()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A path in a directory of its own under the system's temporary directory
+    fn temp_path(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("swaywm-alttab-{}-{test}", std::process::id()));
+        dir.join("nested").join("state.json")
+    }
+
+    #[test]
+    fn saved_order_is_loaded_back() {
+        let path = temp_path("round-trip");
+        let names = ["3", "1:mail", "weird \"name\""].map(String::from);
+        save(&path, &names).unwrap();
+        assert_eq!(load(&path).unwrap(), names);
+
+        // Overwritten as a whole
+        save(&path, &names[..1]).unwrap();
+        assert_eq!(load(&path).unwrap(), &names[..1]);
+        assert!(!path.with_extension("json.tmp").exists());
+
+        std::fs::remove_dir_all(path.parent().unwrap().parent().unwrap()).unwrap();
+        assert!(load(&path).is_err());
+    }
+}
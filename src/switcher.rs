@@ -1,64 +1,726 @@
-use std::collections::VecDeque;
-use std::sync::mpsc::Receiver;
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
 
+use super::clock::Clock;
+use super::notifier::Notifier;
+use super::state;
+use super::template::{self, CommandTemplate};
 use super::WorkspaceSwitcherEvent;
 
+pub const ON_SWITCH_PLACEHOLDERS: &[&str] = &["workspace", "output", "con_id"];
+pub const SWITCH_COMMAND_PLACEHOLDERS: &[&str] = &["name", "num", "id"];
+
+// A ring entry which isn't a workspace, committing to it runs a sway command instead of
+// switching the workspace (e.g. `scratchpad show`)
+#[derive(Clone, Debug)]
+pub struct VirtualEntry {
+    pub label: String,
+    pub command: String,
+}
+
+impl std::str::FromStr for VirtualEntry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((label, command)) if !label.is_empty() && !command.trim().is_empty() => Ok(Self {
+                label: label.to_string(),
+                command: command.to_string(),
+            }),
+            _ => Err("expected LABEL=COMMAND".to_string()),
+        }
+    }
+}
+
+// The order in which the trigger cycles through the workspaces
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum CycleMode {
+    // Most recently used first
+    Mru,
+    // Ascending container id, which is the order sway has created the workspaces in
+    Creation,
+    // The windows of all workspaces instead of the workspaces, most recently focused first
+    Windows,
+}
+
+// What the trigger does on the last entry (and the reverse trigger on the first one)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Boundary {
+    // Go back to the first entry
+    Wrap,
+    // Stay on the last entry
+    Clamp,
+    // Stay on the last entry, and tell the notifier that the end has been reached
+    ClampFlash,
+}
+
+// Where newly created workspaces are placed in the list
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum NewWorkspacePosition {
+    // First, even ahead of the focused workspace, until another workspace is focused
+    Front,
+    // Right after the focused workspace, the next trigger press goes there
+    Second,
+    Back,
+}
+
+pub struct SwitcherConfig {
+    // Sway command to focus a workspace, see SWITCH_COMMAND_PLACEHOLDERS
+    pub switch_command: String,
+    pub mode: CycleMode,
+    pub boundary: Boundary,
+    pub new_workspace_position: NewWorkspacePosition,
+    // Command spawned after each committed switch, see ON_SWITCH_PLACEHOLDERS
+    pub on_switch: Option<CommandTemplate>,
+    // Told about each committed switch
+    pub notifier: Box<dyn Notifier>,
+    // Only switch to the selected workspace at the end of the sequence instead of on each trigger
+    pub preview: bool,
+    // Keep the sequence going when something else changes the focus during it, switching
+    // back to the workspace the sequence is on, instead of ending the sequence
+    pub ignore_external_focus: bool,
+    // Entries placed into the ring along with the workspaces, with ids -1, -2, etc.
+    pub virtual_entries: Vec<VirtualEntry>,
+    // Move the cursor to the output of the workspace switched to, if it's a different one
+    pub warp_cursor: bool,
+    // Workspaces on these outputs are never listed
+    pub excluded_outputs: Vec<String>,
+    // Only the workspaces of the focused output are cycled through, each output has its own
+    // order
+    pub per_output: bool,
+    // Workspaces with names matching any of these are never listed (nor their windows)
+    pub ignored_workspaces: Vec<regex::Regex>,
+    // Where the workspace order is kept across restarts (not used in the windows mode)
+    pub state_file: Option<std::path::PathBuf>,
+    // Commit the selection when no events arrive for this long during the sequence
+    pub sequence_timeout: Option<Duration>,
+}
+
+// The sway IPC requests the switcher makes, served by the sway connection (or by a fake sway in
+// the tests)
+pub trait SwayIpc: Send {
+    fn get_tree(&mut self) -> swayipc::Fallible<swayipc::Node>;
+    fn get_workspaces(&mut self) -> swayipc::Fallible<Vec<swayipc::Workspace>>;
+    fn run_command(&mut self, command: &str) -> swayipc::Fallible<Vec<swayipc::Fallible<()>>>;
+}
+
+impl SwayIpc for swayipc::Connection {
+    fn get_tree(&mut self) -> swayipc::Fallible<swayipc::Node> {
+        swayipc::Connection::get_tree(self)
+    }
+
+    fn get_workspaces(&mut self) -> swayipc::Fallible<Vec<swayipc::Workspace>> {
+        swayipc::Connection::get_workspaces(self)
+    }
+
+    fn run_command(&mut self, command: &str) -> swayipc::Fallible<Vec<swayipc::Fallible<()>>> {
+        swayipc::Connection::run_command(self, command)
+    }
+}
+
+impl SwitcherConfig {
+    fn is_ignored_workspace(&self, name: Option<&str>) -> bool {
+        name.is_some_and(|name| {
+            AltTabWorkspaceSwitcher::is_internal(Some(name))
+                || self.ignored_workspaces.iter().any(|re| re.is_match(name))
+        })
+    }
+}
+
 pub struct AltTabWorkspaceSwitcher {
     evt_rx: Receiver<WorkspaceSwitcherEvent>,
+    config: SwitcherConfig,
     // Sway IPC connection
-    sway_ipc: swayipc::Connection,
+    sway_ipc: Box<dyn SwayIpc>,
     // Workspace IDs in the most to least recently used order
     mru_workspaces: VecDeque<i64>,
     // Count of tab keypresses in a row, zero means the tab sequence is not triggered
     // Always a valid index for mru_workspaces
     tab_count: usize,
+    // Names of the listed workspaces for logging and notifications, kept up to date by the
+    // workspace events
+    workspace_names: HashMap<i64, String>,
+    // Workspaces always placed right after the focused one, in the order they were pinned
+    pinned: Vec<i64>,
+    // Workspaces removed from the list with RemoveCurrent, they're back once focused again
+    removed: Vec<i64>,
+    // Windows of the focused workspace from the most to least recently focused, taken when the
+    // window trigger is pressed first in a sequence, empty when windows aren't being cycled
+    windows: Vec<i64>,
+    // Index of the selected window in windows
+    window_count: usize,
+    // When the last event other than a sway event has been received, for sequence_timeout
+    last_input: Instant,
+    clock: Box<dyn Clock>,
+    // The focused container is moved along with each switch until the sequence ends
+    moving: bool,
+    // The workspace names last written to the state file
+    saved_order: Vec<String>,
+    // With per_output, the entries of the other outputs are set aside for the sequence, in
+    // their order
+    other_outputs: Vec<i64>,
 }
 
 impl AltTabWorkspaceSwitcher {
-    pub fn new(evt_rx: Receiver<WorkspaceSwitcherEvent>) -> Self {
-        let sway_ipc =
-            swayipc::Connection::new().expect("sway IPC socket should be available for connection");
+    pub fn new(
+        evt_rx: Receiver<WorkspaceSwitcherEvent>,
+        config: SwitcherConfig,
+        mut sway_ipc: Box<dyn SwayIpc>,
+        clock: Box<dyn Clock>,
+    ) -> Self {
+        // Seed the list with the existing workspaces at once, the focused one goes first as the
+        // most recently used, and there's no way to know the order of the others
+        let entries = match config.mode {
+            CycleMode::Windows => {
+                let tree = sway_ipc
+                    .get_tree()
+                    .expect("can't get container tree via sway IPC");
+                Self::windows_by_focus(&tree, &config)
+            }
+            _ => {
+                let mut workspaces = sway_ipc
+                    .get_workspaces()
+                    .expect("can't get workspaces via sway IPC");
+                workspaces.retain(|ws| {
+                    !config.excluded_outputs.contains(&ws.output)
+                        && !config.is_ignored_workspace(Some(&ws.name))
+                });
+                workspaces.sort_by_key(|ws| !ws.focused);
+                workspaces.into_iter().map(|ws| (ws.id, ws.name)).collect()
+            }
+        };
+        let mut mru_workspaces = entries.iter().map(|&(id, _)| id).collect::<VecDeque<_>>();
+        let workspace_names = entries.into_iter().collect();
+
+        let saved_order = match &config.state_file {
+            Some(path) if config.mode != CycleMode::Windows && path.exists() => state::load(path)
+                .unwrap_or_else(|e| {
+                    log::warn!("Can't restore the workspace order: {e}");
+                    Vec::new()
+                }),
+            _ => Vec::new(),
+        };
+        Self::restore_order(&mut mru_workspaces, &workspace_names, &saved_order);
+
+        log::debug!(
+            "Initialized workspace switcher with {} existing {}",
+            mru_workspaces.len(),
+            match config.mode {
+                CycleMode::Windows => "windows",
+                _ => "workspaces",
+            }
+        );
 
-        log::debug!("Initialized workspace switcher");
+        // Sway container ids are positive, so negative ones are free to use for virtual entries
+        mru_workspaces.extend((1..=config.virtual_entries.len() as i64).map(|i| -i));
 
         Self {
             evt_rx,
+            config,
             sway_ipc,
-            mru_workspaces: VecDeque::new(),
+            mru_workspaces,
             tab_count: 0,
+            workspace_names,
+            pinned: Vec::new(),
+            removed: Vec::new(),
+            windows: Vec::new(),
+            window_count: 0,
+            last_input: clock.now(),
+            clock,
+            moving: false,
+            other_outputs: Vec::new(),
+            saved_order,
+        }
+    }
+
+    // Arranges the workspaces after the focused one in the saved order, the ones not saved
+    // go last in the order they're in
+    fn restore_order(
+        mru_workspaces: &mut VecDeque<i64>,
+        workspace_names: &HashMap<i64, String>,
+        saved_order: &[String],
+    ) {
+        if saved_order.is_empty() || mru_workspaces.is_empty() {
+            return;
+        }
+        let position = |id: &i64| {
+            workspace_names
+                .get(id)
+                .and_then(|name| saved_order.iter().position(|saved| saved == name))
+                .unwrap_or(usize::MAX)
+        };
+        mru_workspaces.make_contiguous()[1..].sort_by_key(position);
+    }
+
+    // Writes the workspace names to the state file if the order has changed since the last
+    // time, not during a sequence when it's in flux
+    fn save_state(&mut self) {
+        if self.config.mode == CycleMode::Windows
+            || self.tab_count != 0
+            || !self.other_outputs.is_empty()
+        {
+            return;
+        }
+        let Some(path) = &self.config.state_file else {
+            return;
+        };
+        let names = self
+            .mru_workspaces
+            .iter()
+            .filter(|&&id| self.virtual_entry(id).is_none())
+            .filter_map(|id| self.workspace_names.get(id).cloned())
+            .collect::<Vec<_>>();
+        if names == self.saved_order {
+            return;
+        }
+        if let Err(e) = state::save(path, &names) {
+            log::warn!("Can't save the workspace order: {e}");
         }
+        // Not retried on each event if it has failed
+        self.saved_order = names;
+    }
+
+    fn virtual_entry(&self, id: i64) -> Option<&VirtualEntry> {
+        let idx = usize::try_from(-id).ok()?.checked_sub(1)?;
+        self.config.virtual_entries.get(idx)
     }
 
     pub fn run(&mut self) {
         log::info!("Starting the workspace switcher...");
 
         loop {
-            let evt = self.evt_rx.recv().expect("can't read from event channel");
-            log::debug!("Processing event: {:?}", evt);
-
-            match evt {
-                WorkspaceSwitcherEvent::Trigger => {
-                    if self.mru_workspaces.is_empty() {
-                        continue;
-                    }
+            let evt = self.next_event();
+            self.handle_event(evt);
+        }
+    }
 
-                    // Switch to the next workspace, wrapping around if currently at the end
-                    self.tab_count = (self.tab_count + 1) % self.mru_workspaces.len();
-                    self.switch_to_workspace(self.mru_workspaces[self.tab_count]);
-                }
-                WorkspaceSwitcherEvent::EndMod => {
-                    if self.mru_workspaces.is_empty() {
-                        continue;
-                    }
-                    self.end_sequence(self.mru_workspaces[self.tab_count]);
+    // Waits for the next event, the sequence timing out is an EndMod
+    fn next_event(&mut self) -> WorkspaceSwitcherEvent {
+        match self.sequence_time_left() {
+            Some(remaining) => match self.evt_rx.recv_timeout(remaining) {
+                Ok(evt) => evt,
+                Err(RecvTimeoutError::Timeout) => {
+                    log::debug!("No key presses for a while, ending the sequence");
+                    WorkspaceSwitcherEvent::EndMod
                 }
-                WorkspaceSwitcherEvent::SwayWsEvent(ws_event) => {
-                    self.handle_ws_event(ws_event.as_ref());
+                Err(RecvTimeoutError::Disconnected) => {
+                    panic!("can't read from event channel")
                 }
+            },
+            None => self.evt_rx.recv().expect("can't read from event channel"),
+        }
+    }
+
+    // The time until the sequence times out, None if there's no sequence or timeout
+    fn sequence_time_left(&self) -> Option<Duration> {
+        // Sway events don't count as activity, they're mostly caused by the switching itself
+        let in_sequence = self.tab_count != 0 || !self.windows.is_empty();
+        let timeout = self.config.sequence_timeout.filter(|_| in_sequence)?;
+        let idle = self.clock.now().saturating_duration_since(self.last_input);
+        Some(timeout.saturating_sub(idle))
+    }
+
+    fn handle_event(&mut self, evt: WorkspaceSwitcherEvent) {
+        log::debug!("Processing event: {:?}", evt);
+        if !matches!(
+            evt,
+            WorkspaceSwitcherEvent::SwayWsEvent(_) | WorkspaceSwitcherEvent::SwayWindowEvent(_)
+        ) {
+            self.last_input = self.clock.now();
+        }
+
+        match evt {
+            WorkspaceSwitcherEvent::Trigger => self.select_adjacent(true),
+            WorkspaceSwitcherEvent::TriggerBack => self.select_adjacent(false),
+            WorkspaceSwitcherEvent::TriggerMove | WorkspaceSwitcherEvent::TriggerMoveBack => {
+                self.moving = true;
+                let forward = matches!(evt, WorkspaceSwitcherEvent::TriggerMove);
+                self.select_adjacent(forward);
+            }
+            WorkspaceSwitcherEvent::WindowTrigger => self.select_adjacent_window(true),
+            WorkspaceSwitcherEvent::WindowTriggerBack => self.select_adjacent_window(false),
+            WorkspaceSwitcherEvent::EndMod => self.commit_selection(),
+            WorkspaceSwitcherEvent::JumpTo(n) => {
+                self.select_entry(n);
+                self.commit_selection();
             }
+            WorkspaceSwitcherEvent::PinCurrent => self.pin_selected(),
+            WorkspaceSwitcherEvent::RemoveCurrent => self.remove_selected(),
+            WorkspaceSwitcherEvent::Cancel => {
+                self.cancel_window_sequence();
+                self.cancel_sequence();
+            }
+            // The list doesn't have the workspaces in the windows mode
+            WorkspaceSwitcherEvent::SwayWsEvent(_) if self.config.mode == CycleMode::Windows => {}
+            WorkspaceSwitcherEvent::SwayWsEvent(ws_event) => {
+                self.handle_ws_event(ws_event.as_ref());
+            }
+            WorkspaceSwitcherEvent::SwayWindowEvent(window_event) => {
+                self.handle_window_event(window_event.as_ref());
+            }
+        }
 
+        if log::log_enabled!(log::Level::Debug) {
             log::debug!("MRU list: {}", self.format_mru_list());
         }
+        self.save_state();
+    }
+
+    // Switches to the selected entry (if it hasn't been done already) and ends the sequence
+    fn commit_selection(&mut self) {
+        self.end_window_sequence();
+        if self.mru_workspaces.is_empty() {
+            self.restore_other_outputs();
+            return;
+        }
+        let selected = self.mru_workspaces[self.tab_count];
+        if self.tab_count != 0 {
+            if let Some(entry) = self.virtual_entry(selected) {
+                let command = entry.command.clone();
+                self.return_to_sequence_start();
+                self.run_virtual_entry_command(&command);
+            } else if self.config.preview {
+                self.switch_to(selected);
+            }
+        }
+        let start = self.mru_workspaces[0];
+        let switched = self.tab_count != 0 && self.virtual_entry(selected).is_none();
+        self.end_sequence(selected);
+        // Only the switches made with the shortcut are announced, not the focus changes made
+        // by something else which end the sequence too
+        if switched {
+            self.announce_switch(selected);
+        }
+        if self.config.warp_cursor && start != selected {
+            self.warp_cursor_on_output_change(start, selected);
+        }
+    }
+
+    // Selects the entry at the index right away, the current selection stays if there's no
+    // such entry
+    fn select_entry(&mut self, idx: usize) {
+        if self.tab_count == 0 {
+            self.restore_other_outputs();
+            self.resync();
+            if self.config.per_output {
+                self.set_aside_other_outputs();
+            }
+        }
+        if idx == 0 || idx >= self.mru_workspaces.len() {
+            log::debug!("No entry {idx} to jump to");
+            return;
+        }
+        if self.tab_count == 0 && self.config.mode == CycleMode::Creation {
+            self.arrange_by_creation();
+        }
+        self.tab_count = idx;
+        let selected = self.mru_workspaces[idx];
+        if !self.config.preview && self.virtual_entry(selected).is_none() {
+            self.switch_to(selected);
+        }
+    }
+
+    // Selects the next (or the previous) entry in the list, wrapping around or staying put at
+    // the ends
+    fn select_adjacent(&mut self, forward: bool) {
+        if self.tab_count == 0 {
+            // Wrapping around to the start of the sequence lands here again
+            self.restore_other_outputs();
+            self.resync();
+        }
+        if self.mru_workspaces.is_empty() {
+            return;
+        }
+        if self.tab_count == 0 && self.config.per_output {
+            self.set_aside_other_outputs();
+        }
+        if self.tab_count == 0 && self.config.mode == CycleMode::Creation {
+            self.arrange_by_creation();
+        }
+
+        let len = self.mru_workspaces.len();
+        let next = match (self.config.boundary, forward) {
+            (Boundary::Wrap, true) => (self.tab_count + 1) % len,
+            (Boundary::Wrap, false) => (self.tab_count + len - 1) % len,
+            (Boundary::Clamp | Boundary::ClampFlash, true) => (self.tab_count + 1).min(len - 1),
+            (Boundary::Clamp | Boundary::ClampFlash, false) => self.tab_count.saturating_sub(1),
+        };
+        if next == self.tab_count {
+            if self.config.boundary == Boundary::ClampFlash {
+                self.config.notifier.boundary_reached();
+            }
+            return;
+        }
+        // Going back to the first entry is going back to where the sequence has started, as
+        // if nothing was selected yet
+        self.tab_count = next;
+        let selected = self.mru_workspaces[self.tab_count];
+        // Virtual entries can't be previewed, their command runs on commit
+        if !self.config.preview && self.virtual_entry(selected).is_none() {
+            self.switch_to(selected);
+        }
+    }
+
+    // Selects the next (or the previous) window of the focused workspace, in the order the
+    // windows have been focused before the sequence
+    fn select_adjacent_window(&mut self, forward: bool) {
+        if self.windows.is_empty() {
+            self.windows = self.focused_workspace_windows();
+            self.window_count = 0;
+        }
+        let len = self.windows.len();
+        if len < 2 {
+            return;
+        }
+
+        let next = match (self.config.boundary, forward) {
+            (Boundary::Wrap, true) => (self.window_count + 1) % len,
+            (Boundary::Wrap, false) => (self.window_count + len - 1) % len,
+            (Boundary::Clamp | Boundary::ClampFlash, true) => (self.window_count + 1).min(len - 1),
+            (Boundary::Clamp | Boundary::ClampFlash, false) => self.window_count.saturating_sub(1),
+        };
+        if next == self.window_count {
+            if self.config.boundary == Boundary::ClampFlash {
+                self.config.notifier.boundary_reached();
+            }
+            return;
+        }
+        self.window_count = next;
+        if !self.config.preview {
+            self.focus_window(self.windows[next]);
+        }
+    }
+
+    fn end_window_sequence(&mut self) {
+        if self.config.preview && self.window_count != 0 {
+            self.focus_window(self.windows[self.window_count]);
+        }
+        self.windows.clear();
+        self.window_count = 0;
+    }
+
+    fn cancel_window_sequence(&mut self) {
+        if !self.config.preview && self.window_count != 0 {
+            self.focus_window(self.windows[0]);
+        }
+        self.windows.clear();
+        self.window_count = 0;
+    }
+
+    fn focus_window(&mut self, con_id: i64) {
+        let command = format!("[con_id={con_id}] focus");
+        log::debug!("Focusing on window with id = {con_id}");
+        match self.sway_ipc.run_command(&command) {
+            Ok(outcomes) => {
+                for e in outcomes.into_iter().filter_map(Result::err) {
+                    log::error!("Can't focus window {con_id}: {e}");
+                }
+            }
+            Err(e) => log::error!("Can't focus window {con_id}: {e}"),
+        }
+    }
+
+    fn focused_workspace_windows(&mut self) -> Vec<i64> {
+        let tree = self
+            .sway_ipc
+            .get_tree()
+            .expect("can't get container tree via sway IPC");
+        let focused = Self::workspaces(&tree)
+            .into_iter()
+            .map(|(_, workspace)| workspace)
+            .find(|workspace| Self::contains_focused(workspace));
+        let mut windows = Vec::new();
+        if let Some(workspace) = focused {
+            Self::collect_windows_by_focus(workspace, &mut windows);
+        }
+        windows.into_iter().map(|window| window.id).collect()
+    }
+
+    // The windows of all outputs with their titles, in the focus order. It's kept for each
+    // container separately, so the windows of a workspace are all listed together, from the
+    // most recently focused workspace.
+    fn windows_by_focus(tree: &swayipc::Node, config: &SwitcherConfig) -> Vec<(i64, String)> {
+        let mut windows = Vec::new();
+        for id in &tree.focus {
+            let Some(output) = tree.nodes.iter().find(|output| output.id == *id) else {
+                continue;
+            };
+            // The scratchpad is a hidden workspace on the __i3 output
+            let name = output.name.as_deref().unwrap_or("");
+            if name == "__i3"
+                || config
+                    .excluded_outputs
+                    .iter()
+                    .any(|excluded| excluded == name)
+            {
+                continue;
+            }
+            for id in &output.focus {
+                let Some(workspace) = output.nodes.iter().find(|ws| ws.id == *id) else {
+                    continue;
+                };
+                if !config.is_ignored_workspace(workspace.name.as_deref()) {
+                    Self::collect_windows_by_focus(workspace, &mut windows);
+                }
+            }
+        }
+        windows
+            .into_iter()
+            .map(|window| (window.id, Self::window_title(window)))
+            .collect()
+    }
+
+    fn window_title(window: &swayipc::Node) -> String {
+        window
+            .name
+            .clone()
+            .or_else(|| window.app_id.clone())
+            .unwrap_or_default()
+    }
+
+    fn contains_focused(node: &swayipc::Node) -> bool {
+        node.focused
+            || node
+                .nodes
+                .iter()
+                .chain(&node.floating_nodes)
+                .any(Self::contains_focused)
+    }
+
+    // Each container lists its children (tiled and floating) in the focus order, from the most
+    // recently focused one, so following it down to the windows gives their focus order
+    fn collect_windows_by_focus<'a>(node: &'a swayipc::Node, windows: &mut Vec<&'a swayipc::Node>) {
+        for id in &node.focus {
+            let Some(child) = node
+                .nodes
+                .iter()
+                .chain(&node.floating_nodes)
+                .find(|child| child.id == *id)
+            else {
+                continue;
+            };
+            if child.nodes.is_empty() && child.floating_nodes.is_empty() {
+                windows.push(child);
+            } else {
+                Self::collect_windows_by_focus(child, windows);
+            }
+        }
+    }
+
+    // Takes the entries of the outputs other than the focused one out of the list for the
+    // sequence. Virtual entries and the ones which can't be found stay.
+    fn set_aside_other_outputs(&mut self) {
+        let tree = self
+            .sway_ipc
+            .get_tree()
+            .expect("can't get container tree via sway IPC");
+        let Some((focused_output, _)) = self.find_entry(&tree, self.mru_workspaces[0]) else {
+            return;
+        };
+        let (current, other): (Vec<i64>, Vec<i64>) =
+            self.mru_workspaces.iter().copied().partition(|&id| {
+                self.virtual_entry(id).is_some()
+                    || self
+                        .find_entry(&tree, id)
+                        .is_none_or(|(output, _)| output.id == focused_output.id)
+            });
+        self.mru_workspaces = current.into();
+        self.other_outputs = other;
+    }
+
+    // Puts the entries set aside for the sequence back, after the ones of the focused output
+    // (the order between the outputs doesn't matter)
+    fn restore_other_outputs(&mut self) {
+        for id in std::mem::take(&mut self.other_outputs) {
+            if !self.mru_workspaces.contains(&id) {
+                self.mru_workspaces.push_back(id);
+            }
+        }
+    }
+
+    // Reorders the list for the creation mode: workspaces by ascending id starting from the
+    // focused one and wrapping around, then the virtual entries. The selection simply advances
+    // through the list from there, like in the MRU mode.
+    fn arrange_by_creation(&mut self) {
+        let focused = self.mru_workspaces[0];
+        let (mut workspaces, virtual_entries): (Vec<i64>, Vec<i64>) =
+            self.mru_workspaces.iter().partition(|&&id| id >= 0);
+        workspaces.sort_unstable();
+        if let Some(position) = workspaces.iter().position(|&id| id == focused) {
+            workspaces.rotate_left(position);
+        }
+        self.mru_workspaces = workspaces.into_iter().chain(virtual_entries).collect();
+    }
+
+    fn workspace_name(&self, id: i64) -> &str {
+        self.workspace_names.get(&id).map_or("", String::as_str)
+    }
+
+    // Toggles the pin on the selected workspace, the list is rearranged when the sequence ends
+    // so the selection stays put
+    fn pin_selected(&mut self) {
+        if self.tab_count == 0 || self.mru_workspaces.is_empty() {
+            return;
+        }
+        let selected = self.mru_workspaces[self.tab_count];
+        if self.virtual_entry(selected).is_some() {
+            return;
+        }
+        if let Some(idx) = self.pinned.iter().position(|&id| id == selected) {
+            self.pinned.remove(idx);
+            log::info!("Unpinned workspace \"{}\"", self.workspace_name(selected));
+        } else {
+            self.pinned.push(selected);
+            log::info!("Pinned workspace \"{}\"", self.workspace_name(selected));
+        }
+    }
+
+    // Places the pinned workspaces right after the focused one
+    fn arrange_pinned(&mut self) {
+        if self.pinned.is_empty() || self.mru_workspaces.is_empty() {
+            return;
+        }
+        let focused = self.mru_workspaces[0];
+        let pinned = self
+            .pinned
+            .iter()
+            .copied()
+            .filter(|&id| id != focused && self.mru_workspaces.contains(&id))
+            .collect::<Vec<_>>();
+        self.mru_workspaces.retain(|id| !pinned.contains(id));
+        for (offset, id) in pinned.into_iter().enumerate() {
+            self.mru_workspaces.insert(1 + offset, id);
+        }
+    }
+
+    // Drops the selected workspace from the list and selects the next one instead
+    fn remove_selected(&mut self) {
+        if self.tab_count == 0 || self.mru_workspaces.is_empty() {
+            return;
+        }
+        let selected = self.mru_workspaces[self.tab_count];
+        if self.virtual_entry(selected).is_some() {
+            return;
+        }
+        log::info!(
+            "Removing workspace \"{}\" from the list",
+            self.workspace_name(selected)
+        );
+        self.mru_workspaces.remove(self.tab_count);
+        self.pinned.retain(|&id| id != selected);
+        self.removed.push(selected);
+
+        if self.tab_count == self.mru_workspaces.len() {
+            self.tab_count = match self.config.boundary {
+                Boundary::Wrap => 0,
+                Boundary::Clamp | Boundary::ClampFlash => self.tab_count - 1,
+            };
+        }
+        // The removed workspace is focused in live mode, move on to the new selection
+        if !self.config.preview {
+            self.ensure_focused(self.mru_workspaces[self.tab_count]);
+        }
     }
 
     fn switch_to_workspace(&mut self, id: i64) {
@@ -66,8 +728,24 @@ impl AltTabWorkspaceSwitcher {
             .sway_ipc
             .get_tree()
             .expect("can't get container tree via sway IPC");
-        let ws_name = Self::workspace_name_by_id(&tree, id)
-            .expect("the id should be associated with an existing workspace (MRU list is probably not in sync)");
+        let Some((_, workspace)) = Self::find_workspace(&tree, id) else {
+            // Its destroy event hasn't arrived yet (or got lost), go on with the ones left
+            log::warn!("Workspace with id = {id} doesn't exist anymore, resyncing the list");
+            self.resync();
+            if self.tab_count != 0 {
+                let last = self.mru_workspaces.len().saturating_sub(1);
+                self.tab_count = self.tab_count.min(last);
+                if self.tab_count != 0 {
+                    self.ensure_focused(self.mru_workspaces[self.tab_count]);
+                }
+            }
+            return;
+        };
+        let ws_name = workspace.name.as_deref().unwrap_or("");
+        if Self::is_internal(Some(ws_name)) {
+            log::warn!("Not switching to sway's internal workspace \"{ws_name}\"");
+            return;
+        }
 
         log::debug!(
             "Focusing on workspace with id = {}, name = \"{}\"",
@@ -75,86 +753,1716 @@ impl AltTabWorkspaceSwitcher {
             ws_name
         );
 
-        self.sway_ipc
-            .run_command(format!("workspace {}", ws_name))
-            .expect("can't switch workspace using sway IPC command")[0]
-            // the only command is `workspace`, its result is at index 0
-            .as_ref()
-            .expect("can't switch workspace using sway IPC command");
+        if self.moving {
+            // Fails when there's nothing to move (e.g. an empty workspace), the switch still
+            // has to happen
+            let command = format!("move container to workspace {ws_name}");
+            log::debug!("Moving the focused container: {command}");
+            match self.sway_ipc.run_command(&command) {
+                Ok(outcomes) => {
+                    for e in outcomes.into_iter().filter_map(Result::err) {
+                        log::warn!("Can't move the focused container: {e}");
+                    }
+                }
+                Err(e) => log::warn!("Can't move the focused container: {e}"),
+            }
+        }
+
+        let command = template::expand(
+            &self.config.switch_command,
+            &[
+                ("name", ws_name),
+                (
+                    "num",
+                    &workspace.num.map(|n| n.to_string()).unwrap_or_default(),
+                ),
+                ("id", &id.to_string()),
+            ],
+        );
+        // The template may contain several commands, the list stays as it is if some fail (the
+        // focus events keep it in line with what has actually happened)
+        match self.sway_ipc.run_command(&command) {
+            Ok(outcomes) => {
+                for e in outcomes.into_iter().filter_map(Result::err) {
+                    log::warn!("Can't switch to workspace \"{ws_name}\" with \"{command}\": {e}");
+                }
+            }
+            Err(e) => log::warn!("Can't switch to workspace \"{ws_name}\": {e}"),
+        }
     }
 
-    fn workspace_name_by_id(tree: &swayipc::Node, id: i64) -> Option<&str> {
-        tree.nodes
-            .iter()
-            .flat_map(|output| output.nodes.iter())
-            .find(|workspace| workspace.id == id)?
-            .name
-            .as_deref()
+    // Focuses the window in the windows mode, switches to the workspace otherwise
+    fn switch_to(&mut self, id: i64) {
+        match self.config.mode {
+            CycleMode::Windows => self.focus_window(id),
+            _ => self.switch_to_workspace(id),
+        }
     }
 
-    fn end_sequence(&mut self, new_ws_id: i64) {
-        if self.tab_count == 0 {
+    // Switches to the workspace (or the window) unless it's already focused (which would
+    // trigger sway's workspace_auto_back_and_forth) or it's a virtual entry
+    fn ensure_focused(&mut self, id: i64) {
+        if self.virtual_entry(id).is_some() {
             return;
         }
-        self.mru_workspaces.retain(|&id| id != new_ws_id);
-        self.mru_workspaces.push_front(new_ws_id);
-        self.tab_count = 0;
+        let focused_id = match self.config.mode {
+            CycleMode::Windows => self
+                .sway_ipc
+                .get_tree()
+                .expect("can't get container tree via sway IPC")
+                .find_as_ref(|node| node.focused)
+                .map(|node| node.id),
+            _ => self
+                .sway_ipc
+                .get_workspaces()
+                .expect("can't get workspaces via sway IPC")
+                .into_iter()
+                .find(|ws| ws.focused)
+                .map(|ws| ws.id),
+        };
+        if focused_id != Some(id) {
+            self.switch_to(id);
+        }
     }
 
-    // Reduces code nesting
-    #[allow(clippy::comparison_chain)]
-    fn handle_ws_event(&mut self, ws_event: &swayipc::WorkspaceEvent) {
-        // Sway workspace event types:
-        // init - add the to the end of the list
-        // empty - remove from the list
-        // focus - move to the beginning of the list
-        // move, rename, urgent, reload - ignore
+    // Ends the sequence without committing the selection, the list stays as it was
+    fn cancel_sequence(&mut self) {
+        if self.tab_count != 0 {
+            // The moved container is carried back too
+            self.return_to_sequence_start();
+            self.tab_count = 0;
+        }
+        self.moving = false;
+        self.restore_other_outputs();
+    }
 
-        // All events we're interested in have `current` workspace field
-        if let Some(current_id) = ws_event.current.as_ref().map(|x| x.id) {
-            match ws_event.change {
-                swayipc::WorkspaceChange::Init => {
-                    self.mru_workspaces.push_back(current_id);
+    // Switches back to the workspace focused before the sequence, if it was left for previews
+    fn return_to_sequence_start(&mut self) {
+        self.ensure_focused(self.mru_workspaces[0]);
+    }
+
+    // Undoes a focus change made by something else during the sequence, so the sequence
+    // continues from the workspace it has been on
+    fn reassert_sequence_focus(&mut self, focused_id: i64) {
+        let expected = if self.config.preview {
+            self.mru_workspaces[0]
+        } else {
+            self.mru_workspaces[self.tab_count]
+        };
+        if focused_id != expected {
+            log::debug!("Ignoring external focus change to workspace {focused_id}");
+            self.ensure_focused(expected);
+        }
+    }
+
+    fn run_virtual_entry_command(&mut self, command: &str) {
+        log::debug!("Running virtual entry command: {}", command);
+        match self.sway_ipc.run_command(command) {
+            Ok(outcomes) => {
+                for e in outcomes.into_iter().filter_map(Result::err) {
+                    log::error!("Virtual entry command \"{command}\" failed: {e}");
                 }
-                swayipc::WorkspaceChange::Empty => {
-                    if let Some(idx) = self.mru_workspaces.iter().position(|&x| x == current_id) {
-                        self.mru_workspaces.remove(idx);
-                        if idx < self.tab_count {
-                            self.tab_count -= 1;
-                        } else if idx == self.tab_count {
-                            panic!("the currently focused workspace is deleted");
-                        }
-                    } else {
-                        log::warn!("Deleting unlisted workspace");
-                    }
+            }
+            Err(e) => log::error!("Can't run virtual entry command \"{command}\": {e}"),
+        }
+    }
+
+    // Returns the output node and the workspace node of the entry, which is the workspace itself
+    // or, in the windows mode, the one the window is on
+    fn find_entry<'a>(
+        &self,
+        tree: &'a swayipc::Node,
+        id: i64,
+    ) -> Option<(&'a swayipc::Node, &'a swayipc::Node)> {
+        match self.config.mode {
+            CycleMode::Windows => Self::workspaces(tree)
+                .into_iter()
+                .find(|(_, workspace)| workspace.find_as_ref(|node| node.id == id).is_some()),
+            _ => Self::find_workspace(tree, id),
+        }
+    }
+
+    // Returns the workspace node and the output node it's placed on
+    fn find_workspace(tree: &swayipc::Node, id: i64) -> Option<(&swayipc::Node, &swayipc::Node)> {
+        Self::workspaces(tree)
+            .into_iter()
+            .find(|(_, workspace)| workspace.id == id)
+    }
+
+    // Lists all workspaces with the outputs they're placed on
+    fn workspaces(tree: &swayipc::Node) -> Vec<(&swayipc::Node, &swayipc::Node)> {
+        let mut workspaces = Vec::new();
+        Self::collect_workspaces(tree, tree, &mut workspaces);
+        workspaces
+    }
+
+    // Searches the whole subtree instead of assuming workspaces are direct children of the
+    // outputs, `output` is the closest output node above `node`
+    fn collect_workspaces<'a>(
+        node: &'a swayipc::Node,
+        output: &'a swayipc::Node,
+        workspaces: &mut Vec<(&'a swayipc::Node, &'a swayipc::Node)>,
+    ) {
+        match node.node_type {
+            // Workspaces can't be nested, no need to look inside
+            swayipc::NodeType::Workspace => workspaces.push((output, node)),
+            swayipc::NodeType::Output => {
+                for child in &node.nodes {
+                    Self::collect_workspaces(child, node, workspaces);
                 }
-                swayipc::WorkspaceChange::Focus => {
-                    if self.tab_count == 0 {
-                        self.mru_workspaces.retain(|&x| x != current_id);
-                        self.mru_workspaces.push_front(current_id);
-                    } else if current_id != self.mru_workspaces[self.tab_count] {
-                        // Tab sequence is active and the workspace switch isn't
-                        // caused by a tab press, stop the sequence
-                        self.end_sequence(current_id);
-                    }
+            }
+            _ => {
+                for child in &node.nodes {
+                    Self::collect_workspaces(child, output, workspaces);
                 }
-                _ => {}
             }
         }
     }
 
-    // For debugging purposes
-    fn format_mru_list(&mut self) -> String {
+    fn is_excluded(&self, output: Option<&str>) -> bool {
+        output.is_some_and(|output| self.config.excluded_outputs.iter().any(|o| o == output))
+    }
+
+    // Sway's own workspaces (like __i3_scratch, the scratchpad) aren't meant to be switched to
+    fn is_internal(name: Option<&str>) -> bool {
+        name.is_some_and(|name| name.starts_with("__"))
+    }
+
+    // Compares the list with the workspaces sway has and fixes it if they differ. The list is
+    // kept up to date by the workspace events, so a difference means some have been missed.
+    fn resync(&mut self) {
         let tree = self
             .sway_ipc
             .get_tree()
             .expect("can't get container tree via sway IPC");
-        format!(
-            "{:?}",
-            self.mru_workspaces
-                .iter()
-                .map(|&id| Self::workspace_name_by_id(&tree, id))
-                .collect::<Vec<_>>()
-        )
+        let existing = match self.config.mode {
+            CycleMode::Windows => Self::windows_by_focus(&tree, &self.config)
+                .into_iter()
+                .collect::<HashMap<_, _>>(),
+            _ => Self::workspaces(&tree)
+                .into_iter()
+                // The scratchpad is a hidden workspace on the __i3 output
+                .filter(|(output, workspace)| {
+                    output.name.as_deref() != Some("__i3")
+                        && !self.is_excluded(output.name.as_deref())
+                        && !self.config.is_ignored_workspace(workspace.name.as_deref())
+                })
+                .map(|(_, workspace)| (workspace.id, workspace.name.clone().unwrap_or_default()))
+                .collect::<HashMap<_, _>>(),
+        };
+        let stale = self
+            .mru_workspaces
+            .iter()
+            .copied()
+            .filter(|&id| self.virtual_entry(id).is_none() && !existing.contains_key(&id))
+            .collect::<Vec<_>>();
+        let mut missing = existing
+            .keys()
+            .copied()
+            .filter(|id| !self.mru_workspaces.contains(id) && !self.removed.contains(id))
+            .collect::<Vec<_>>();
+        missing.sort_unstable();
+        if stale.is_empty() && missing.is_empty() {
+            return;
+        }
+
+        log::warn!(
+            "The workspace list is out of sync with sway ({} difference(s)), removing \
+            workspaces which don't exist anymore: {:?}, adding unlisted ones: {:?}",
+            stale.len() + missing.len(),
+            stale,
+            missing
+        );
+        self.mru_workspaces.retain(|id| !stale.contains(id));
+        self.mru_workspaces.extend(missing);
+        self.workspace_names = existing;
+    }
+
+    fn end_sequence(&mut self, new_ws_id: i64) {
+        self.moving = false;
+        self.restore_other_outputs();
+        if self.tab_count == 0 {
+            return;
+        }
+        self.mru_workspaces.retain(|&id| id != new_ws_id);
+        if self.virtual_entry(new_ws_id).is_some() {
+            // The workspace in front stays focused after running the virtual entry's command
+            let position = self.mru_workspaces.len().min(1);
+            self.mru_workspaces.insert(position, new_ws_id);
+        } else {
+            self.mru_workspaces.push_front(new_ws_id);
+        }
+        self.tab_count = 0;
+        self.arrange_pinned();
+    }
+
+    // Tells the notifier and the on_switch hook about the committed switch
+    fn announce_switch(&mut self, ws_id: i64) {
+        if let Some(name) = self.workspace_names.get(&ws_id) {
+            self.config.notifier.notify(name);
+        }
+        if self.config.on_switch.is_some() {
+            self.run_on_switch_hook(ws_id);
+        }
+    }
+
+    // Moves the cursor to the center of the new workspace's output, if it's not the output
+    // the sequence has started on (sway moves only the keyboard focus there)
+    fn warp_cursor_on_output_change(&mut self, from_ws_id: i64, to_ws_id: i64) {
+        let tree = self
+            .sway_ipc
+            .get_tree()
+            .expect("can't get container tree via sway IPC");
+        let (Some((from_output, _)), Some((to_output, _))) = (
+            self.find_entry(&tree, from_ws_id),
+            self.find_entry(&tree, to_ws_id),
+        ) else {
+            return;
+        };
+        if from_output.id == to_output.id {
+            return;
+        }
+
+        let rect = &to_output.rect;
+        let command = format!(
+            "seat - cursor set {} {}",
+            rect.x + rect.width / 2,
+            rect.y + rect.height / 2
+        );
+        log::debug!("Warping the cursor: {command}");
+        match self.sway_ipc.run_command(&command) {
+            Ok(outcomes) => {
+                for e in outcomes.into_iter().filter_map(Result::err) {
+                    log::warn!("Can't warp the cursor: {e}");
+                }
+            }
+            Err(e) => log::warn!("Can't warp the cursor: {e}"),
+        }
+    }
+
+    // Spawns the on_switch command without waiting for it to finish
+    fn run_on_switch_hook(&mut self, ws_id: i64) {
+        let Some(on_switch) = &self.config.on_switch else {
+            return;
+        };
+        let tree = self
+            .sway_ipc
+            .get_tree()
+            .expect("can't get container tree via sway IPC");
+        let Some((output, workspace)) = self.find_entry(&tree, ws_id) else {
+            log::warn!("Not running the on_switch hook, workspace {ws_id} doesn't exist");
+            return;
+        };
+
+        let argv = on_switch.expand(&[
+            ("workspace", workspace.name.as_deref().unwrap_or("")),
+            ("output", output.name.as_deref().unwrap_or("")),
+            ("con_id", &ws_id.to_string()),
+        ]);
+        log::debug!("Running the on_switch hook: {:?}", argv);
+
+        match std::process::Command::new(&argv[0])
+            .args(&argv[1..])
+            .spawn()
+        {
+            // Reap the process in the background so it doesn't become a zombie
+            Ok(mut child) => {
+                std::thread::spawn(move || child.wait());
+            }
+            Err(e) => log::error!("Can't run the on_switch hook: {e}"),
+        }
+    }
+
+    // Reduces code nesting
+    #[allow(clippy::comparison_chain)]
+    fn handle_ws_event(&mut self, ws_event: &swayipc::WorkspaceEvent) {
+        // Sway workspace event types:
+        // init - add to the list, at the end by default
+        // empty - remove from the list
+        // focus - move to the beginning of the list
+        // move - add or remove if moved from or to an excluded output
+        // rename - add or remove if renamed from or to an ignored name
+        // urgent, reload - ignore
+
+        if let Some(current) = &ws_event.current {
+            if ws_event.change == swayipc::WorkspaceChange::Empty {
+                self.workspace_names.remove(&current.id);
+            } else if let Some(name) = &current.name {
+                self.workspace_names.insert(current.id, name.clone());
+            }
+        }
+
+        // All events we're interested in have `current` workspace field
+        if let Some(current_id) = ws_event.current.as_ref().map(|x| x.id) {
+            let excluded = ws_event.current.as_ref().is_some_and(|ws| {
+                self.is_excluded(ws.output.as_deref())
+                    || self.config.is_ignored_workspace(ws.name.as_deref())
+            });
+            match ws_event.change {
+                // Workspaces on excluded outputs (and sway's internal ones) aren't recorded at all
+                swayipc::WorkspaceChange::Init | swayipc::WorkspaceChange::Focus if excluded => {}
+                // The workspace may be already listed if it has been created between seeding
+                // the list and subscribing to the events
+                swayipc::WorkspaceChange::Init if !self.mru_workspaces.contains(&current_id) => {
+                    self.insert_new(current_id);
+                }
+                swayipc::WorkspaceChange::Empty => {
+                    let was_removed = self.removed.contains(&current_id);
+                    let was_set_aside = self.other_outputs.contains(&current_id);
+                    self.pinned.retain(|&x| x != current_id);
+                    self.removed.retain(|&x| x != current_id);
+                    self.other_outputs.retain(|&x| x != current_id);
+                    if let Some(idx) = self.mru_workspaces.iter().position(|&x| x == current_id) {
+                        self.mru_workspaces.remove(idx);
+                        if idx < self.tab_count {
+                            self.tab_count -= 1;
+                        } else if idx == self.tab_count {
+                            if !self.config.preview {
+                                panic!("the currently focused workspace is deleted");
+                            }
+                            // The selected workspace isn't focused in preview mode, so it can
+                            // disappear, select the next one (or the previous one if it was last)
+                            self.tab_count = self.tab_count.min(self.mru_workspaces.len() - 1);
+                        }
+                    } else if !excluded && !was_removed && !was_set_aside {
+                        log::warn!("Deleting unlisted workspace");
+                    }
+                }
+                swayipc::WorkspaceChange::Move | swayipc::WorkspaceChange::Rename => {
+                    let idx = self.mru_workspaces.iter().position(|&x| x == current_id);
+                    match idx {
+                        // The selected workspace stays until the end of the sequence
+                        Some(idx) if excluded && (self.tab_count == 0 || idx != self.tab_count) => {
+                            self.mru_workspaces.remove(idx);
+                            if idx < self.tab_count {
+                                self.tab_count -= 1;
+                            }
+                        }
+                        None if !excluded && !self.removed.contains(&current_id) => {
+                            self.mru_workspaces.push_back(current_id)
+                        }
+                        _ => {}
+                    }
+                }
+                swayipc::WorkspaceChange::Focus => self.on_focus(current_id),
+                _ => {}
+            }
+        }
+    }
+
+    // Window events keep the list up to date in the windows mode, like the workspace events
+    // do otherwise
+    fn handle_window_event(&mut self, window_event: &swayipc::WindowEvent) {
+        if self.config.mode != CycleMode::Windows {
+            return;
+        }
+        let window = &window_event.container;
+        match window_event.change {
+            swayipc::WindowChange::New | swayipc::WindowChange::Focus
+                if self.is_window_excluded(window.id) => {}
+            swayipc::WindowChange::New if !self.mru_workspaces.contains(&window.id) => {
+                self.workspace_names
+                    .insert(window.id, Self::window_title(window));
+                self.insert_new(window.id);
+            }
+            swayipc::WindowChange::Title => {
+                self.workspace_names
+                    .insert(window.id, Self::window_title(window));
+            }
+            swayipc::WindowChange::Close => {
+                self.workspace_names.remove(&window.id);
+                self.pinned.retain(|&x| x != window.id);
+                self.removed.retain(|&x| x != window.id);
+                if let Some(idx) = self.mru_workspaces.iter().position(|&x| x == window.id) {
+                    self.mru_workspaces.remove(idx);
+                    // Unlike the workspaces, the selected window may be closed in any mode,
+                    // the next one is selected then. Sway focuses some window in its place, which
+                    // ends the sequence (unless external focus changes are ignored).
+                    if idx < self.tab_count {
+                        self.tab_count -= 1;
+                    } else if idx == self.tab_count {
+                        let last = self.mru_workspaces.len().saturating_sub(1);
+                        self.tab_count = self.tab_count.min(last);
+                    }
+                }
+            }
+            swayipc::WindowChange::Focus => {
+                self.workspace_names
+                    .entry(window.id)
+                    .or_insert_with(|| Self::window_title(window));
+                self.on_focus(window.id);
+            }
+            _ => {}
+        }
+    }
+
+    // Whether the window is on an excluded output, only looked up if there are any
+    fn is_window_excluded(&mut self, id: i64) -> bool {
+        if self.config.excluded_outputs.is_empty() && self.config.ignored_workspaces.is_empty() {
+            return false;
+        }
+        let tree = self
+            .sway_ipc
+            .get_tree()
+            .expect("can't get container tree via sway IPC");
+        self.find_entry(&tree, id)
+            .is_some_and(|(output, workspace)| {
+                self.is_excluded(output.name.as_deref())
+                    || self.config.is_ignored_workspace(workspace.name.as_deref())
+            })
+    }
+
+    // Places a new entry into the list according to new_workspace_position
+    fn insert_new(&mut self, id: i64) {
+        let idx = match self.config.new_workspace_position {
+            NewWorkspacePosition::Front => 0,
+            NewWorkspacePosition::Second => self.mru_workspaces.len().min(1),
+            NewWorkspacePosition::Back => self.mru_workspaces.len(),
+        };
+        self.mru_workspaces.insert(idx, id);
+        // Keep the same entry selected during a sequence
+        if self.tab_count != 0 && idx <= self.tab_count {
+            self.tab_count += 1;
+        }
+    }
+
+    // Moves the focused entry to the front of the list, or handles the focus change during the
+    // sequence
+    fn on_focus(&mut self, id: i64) {
+        if self.tab_count == 0 {
+            self.removed.retain(|&x| x != id);
+            self.mru_workspaces.retain(|&x| x != id);
+            self.mru_workspaces.push_front(id);
+            self.arrange_pinned();
+        } else if self.config.ignore_external_focus {
+            self.reassert_sequence_focus(id);
+        } else if id != self.mru_workspaces[self.tab_count] {
+            // Tab sequence is active and the focus change isn't caused by a tab press, stop
+            // the sequence
+            self.end_sequence(id);
+        }
+    }
+
+    // For debugging purposes
+    fn format_mru_list(&self) -> String {
+        format!(
+            "{:?}",
+            self.mru_workspaces
+                .iter()
+                .map(|&id| self
+                    .virtual_entry(id)
+                    .map(|entry| entry.label.as_str())
+                    .or_else(|| self.workspace_names.get(&id).map(String::as_str)))
+                .collect::<Vec<_>>()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::Sender;
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::notifier::NoopNotifier;
+
+    // The outputs of the fake sway, side by side
+    const OUTPUTS: [&str; 2] = ["eDP-1", "HDMI-A-1"];
+
+    struct FakeWorkspace {
+        id: i64,
+        name: String,
+        output: String,
+    }
+
+    struct FakeWindow {
+        id: i64,
+        title: String,
+        workspace: i64,
+    }
+
+    #[derive(Default)]
+    struct FakeSwayState {
+        workspaces: Vec<FakeWorkspace>,
+        focused: i64,
+        // The windows in the focus order, the most recently focused one first
+        windows: Vec<FakeWindow>,
+        // The commands run so far, and the sway events waiting to be delivered
+        commands: Vec<String>,
+        events: VecDeque<WorkspaceSwitcherEvent>,
+        // Commands which fail
+        failing: Vec<String>,
+    }
+
+    // Answers the switcher's requests from its workspace list and records the commands, the
+    // workspace commands change the focus like sway does
+    #[derive(Clone, Default)]
+    struct FakeSway(Arc<Mutex<FakeSwayState>>);
+
+    fn node(id: i64, name: &str, node_type: &str, x: i32, nodes: Vec<Value>) -> Value {
+        let rect = json!({"x": x, "y": 0, "width": 1920, "height": 1080});
+        json!({
+            "id": id, "name": name, "type": node_type, "border": "none",
+            "current_border_width": 0, "layout": "splith", "percent": null, "rect": rect,
+            "window_rect": rect, "deco_rect": rect, "geometry": rect, "urgent": false,
+            "focused": false, "focus": [], "nodes": nodes, "floating_nodes": [], "sticky": false,
+        })
+    }
+
+    impl FakeSwayState {
+        fn num(name: &str) -> i32 {
+            name.split(':').next().unwrap().parse().unwrap_or(-1)
+        }
+
+        fn output_x(output: &str) -> i32 {
+            OUTPUTS.iter().position(|&o| o == output).unwrap_or(0) as i32 * 1920
+        }
+
+        fn workspace_node(&self, ws: &FakeWorkspace) -> Value {
+            let windows = self
+                .windows
+                .iter()
+                .filter(|window| window.workspace == ws.id);
+            let mut node = node(
+                ws.id,
+                &ws.name,
+                "workspace",
+                Self::output_x(&ws.output),
+                windows
+                    .clone()
+                    .map(|window| self.window_node(window))
+                    .collect(),
+            );
+            node["num"] = json!(Self::num(&ws.name));
+            node["output"] = json!(ws.output);
+            node["focused"] = json!(ws.id == self.focused && node["nodes"] == json!([]));
+            node["focus"] = json!(windows.map(|window| window.id).collect::<Vec<_>>());
+            node
+        }
+
+        fn window_node(&self, window: &FakeWindow) -> Value {
+            let mut node = node(window.id, &window.title, "con", 0, vec![]);
+            node["focused"] = json!(self.windows.first().map(|w| w.id) == Some(window.id));
+            node
+        }
+
+        // Where the most recently focused window of the workspace is in the focus order
+        fn focus_rank(&self, workspace: i64) -> usize {
+            self.windows
+                .iter()
+                .position(|window| window.workspace == workspace)
+                .unwrap_or(usize::MAX)
+        }
+
+        // The scratchpad is on the __i3 output, like in sway
+        fn tree(&self) -> Value {
+            let mut scratch = node(3, "__i3_scratch", "workspace", 0, vec![]);
+            scratch["output"] = json!("__i3");
+            let mut outputs = vec![node(2, "__i3", "output", 0, vec![scratch])];
+            for (i, &output) in OUTPUTS.iter().enumerate() {
+                let workspaces = self
+                    .workspaces
+                    .iter()
+                    .filter(|ws| ws.output == output)
+                    .map(|ws| self.workspace_node(ws))
+                    .collect();
+                let x = Self::output_x(output);
+                let mut output_node = node(10 + i as i64, output, "output", x, workspaces);
+                let mut focus = self
+                    .workspaces
+                    .iter()
+                    .filter(|ws| ws.output == output)
+                    .map(|ws| ws.id)
+                    .collect::<Vec<_>>();
+                focus.sort_by_key(|&id| self.focus_rank(id));
+                output_node["focus"] = json!(focus);
+                outputs.push(output_node);
+            }
+            // The containers with the most recently focused windows come first in the focus
+            // lists, like in sway
+            let mut focus = (0..OUTPUTS.len()).collect::<Vec<_>>();
+            focus.sort_by_key(|&i| {
+                let workspaces = self.workspaces.iter().filter(|ws| ws.output == OUTPUTS[i]);
+                workspaces.map(|ws| self.focus_rank(ws.id)).min()
+            });
+            let mut root = node(1, "root", "root", 0, outputs);
+            root["focus"] = json!(focus.iter().map(|&i| 10 + i as i64).collect::<Vec<_>>());
+            root
+        }
+
+        fn workspace_list(&self) -> Value {
+            let workspaces = self.workspaces.iter().map(|ws| {
+                json!({
+                    "id": ws.id, "num": Self::num(&ws.name), "name": ws.name, "visible": true,
+                    "focused": ws.id == self.focused, "urgent": false, "representation": null,
+                    "rect": {"x": Self::output_x(&ws.output), "y": 0, "width": 1920, "height": 1080},
+                    "output": ws.output,
+                })
+            });
+            Value::Array(workspaces.collect())
+        }
+
+        fn push_event(&mut self, change: &str, id: i64, old: Option<i64>) {
+            let node = |id| {
+                let ws = self.workspaces.iter().find(|ws| ws.id == id)?;
+                Some(self.workspace_node(ws))
+            };
+            let event = json!({"change": change, "current": node(id), "old": old.and_then(node)});
+            let event = serde_json::from_value(event).unwrap();
+            self.events
+                .push_back(WorkspaceSwitcherEvent::SwayWsEvent(Box::new(event)));
+        }
+
+        fn push_window_event(&mut self, change: &str, id: i64) {
+            let window = self.windows.iter().find(|window| window.id == id).unwrap();
+            let event = json!({"change": change, "container": self.window_node(window)});
+            let event = serde_json::from_value(event).unwrap();
+            self.events
+                .push_back(WorkspaceSwitcherEvent::SwayWindowEvent(Box::new(event)));
+        }
+
+        fn focus(&mut self, id: i64) {
+            let old = std::mem::replace(&mut self.focused, id);
+            self.push_event("focus", id, Some(old));
+        }
+
+        // Focuses the window and its workspace
+        fn focus_window(&mut self, id: i64) {
+            let idx = self
+                .windows
+                .iter()
+                .position(|window| window.id == id)
+                .unwrap();
+            let window = self.windows.remove(idx);
+            let workspace = window.workspace;
+            self.windows.insert(0, window);
+            if workspace != self.focused {
+                self.focus(workspace);
+            }
+            self.push_window_event("focus", id);
+        }
+
+        fn run(&mut self, command: &str) -> swayipc::Fallible<()> {
+            if self.failing.iter().any(|failing| failing == command) {
+                return Err(swayipc::Error::CommandFailed(format!(
+                    "can't run {command}"
+                )));
+            }
+            if let Some(con_id) = command
+                .strip_prefix("[con_id=")
+                .and_then(|rest| rest.strip_suffix("] focus"))
+            {
+                let id = con_id.parse().unwrap();
+                if !self.windows.iter().any(|window| window.id == id) {
+                    return Err(swayipc::Error::CommandFailed(format!("no window {id}")));
+                }
+                self.focus_window(id);
+                return Ok(());
+            }
+            let Some(target) = command.strip_prefix("workspace ") else {
+                return Ok(());
+            };
+            let target = target.trim_start_matches("--no-auto-back-and-forth ");
+            let ws = match target.strip_prefix("number ") {
+                Some(num) => self
+                    .workspaces
+                    .iter()
+                    .find(|ws| Some(Self::num(&ws.name)) == num.parse().ok()),
+                None => self.workspaces.iter().find(|ws| ws.name == target),
+            };
+            let id = ws
+                .map(|ws| ws.id)
+                .ok_or_else(|| swayipc::Error::CommandFailed(format!("no workspace {target}")))?;
+            if id != self.focused {
+                self.focus(id);
+            }
+            Ok(())
+        }
+    }
+
+    impl SwayIpc for FakeSway {
+        fn get_tree(&mut self) -> swayipc::Fallible<swayipc::Node> {
+            Ok(serde_json::from_value(self.0.lock().unwrap().tree())?)
+        }
+
+        fn get_workspaces(&mut self) -> swayipc::Fallible<Vec<swayipc::Workspace>> {
+            Ok(serde_json::from_value(
+                self.0.lock().unwrap().workspace_list(),
+            )?)
+        }
+
+        fn run_command(&mut self, command: &str) -> swayipc::Fallible<Vec<swayipc::Fallible<()>>> {
+            let mut state = self.0.lock().unwrap();
+            state.commands.push(command.to_string());
+            Ok(command.split(';').map(|c| state.run(c.trim())).collect())
+        }
+    }
+
+    impl FakeSway {
+        // Workspaces by name and output, the first one is focused
+        fn new(workspaces: &[(&str, &str)]) -> Self {
+            let sway = Self::default();
+            for &(name, output) in workspaces {
+                sway.create(name, output);
+            }
+            let mut state = sway.0.lock().unwrap();
+            state.focused = state.workspaces.first().map_or(0, |ws| ws.id);
+            state.events.clear();
+            drop(state);
+            sway
+        }
+
+        fn create(&self, name: &str, output: &str) -> i64 {
+            let mut state = self.0.lock().unwrap();
+            let id = 100 + state.workspaces.len() as i64;
+            state.workspaces.push(FakeWorkspace {
+                id,
+                name: name.to_string(),
+                output: output.to_string(),
+            });
+            state.push_event("init", id, None);
+            id
+        }
+
+        fn id(&self, name: &str) -> i64 {
+            let state = self.0.lock().unwrap();
+            state
+                .workspaces
+                .iter()
+                .find(|ws| ws.name == name)
+                .unwrap()
+                .id
+        }
+
+        // A focus change made by something else than the switcher
+        fn focus(&self, name: &str) {
+            let id = self.id(name);
+            self.0.lock().unwrap().focus(id);
+        }
+
+        // Opens a window on the workspace, behind the other windows in the focus order
+        fn open_window(&self, title: &str, workspace: &str) -> i64 {
+            let workspace = self.id(workspace);
+            let mut state = self.0.lock().unwrap();
+            let id = 200 + state.windows.len() as i64;
+            state.windows.push(FakeWindow {
+                id,
+                title: title.to_string(),
+                workspace,
+            });
+            state.push_window_event("new", id);
+            id
+        }
+
+        fn window_id(&self, title: &str) -> i64 {
+            let state = self.0.lock().unwrap();
+            let window = state.windows.iter().find(|window| window.title == title);
+            window.unwrap().id
+        }
+
+        fn get_tree_json(&self) -> Value {
+            self.0.lock().unwrap().tree()
+        }
+
+        fn fail(&self, command: &str) {
+            self.0.lock().unwrap().failing.push(command.to_string());
+        }
+
+        fn take_commands(&self) -> Vec<String> {
+            std::mem::take(&mut self.0.lock().unwrap().commands)
+        }
+    }
+
+    // Records the notified workspaces
+    #[derive(Clone, Default)]
+    struct RecordingNotifier(Arc<Mutex<Vec<String>>>);
+
+    impl Notifier for RecordingNotifier {
+        fn notify(&mut self, workspace: &str) {
+            self.0.lock().unwrap().push(workspace.to_string());
+        }
+
+        fn boundary_reached(&mut self) {
+            self.0.lock().unwrap().push("boundary".to_string());
+        }
+    }
+
+    fn config() -> SwitcherConfig {
+        SwitcherConfig {
+            switch_command: "workspace {name}".to_string(),
+            mode: CycleMode::Mru,
+            boundary: Boundary::Wrap,
+            new_workspace_position: NewWorkspacePosition::Back,
+            on_switch: None,
+            notifier: Box::new(NoopNotifier),
+            preview: false,
+            ignore_external_focus: false,
+            virtual_entries: Vec::new(),
+            warp_cursor: false,
+            excluded_outputs: Vec::new(),
+            per_output: false,
+            ignored_workspaces: Vec::new(),
+            state_file: None,
+            sequence_timeout: None,
+        }
+    }
+
+    // The switcher with the fake sway, the workspace events caused by the switcher are
+    // delivered to it right after each event
+    struct Harness {
+        switcher: AltTabWorkspaceSwitcher,
+        sway: FakeSway,
+        clock: ManualClock,
+        evt_tx: Sender<WorkspaceSwitcherEvent>,
+    }
+
+    impl Harness {
+        fn new(sway: &FakeSway, config: SwitcherConfig) -> Self {
+            let (evt_tx, evt_rx) = std::sync::mpsc::channel();
+            let clock = ManualClock::default();
+            let sway_ipc = Box::new(sway.clone());
+            let switcher =
+                AltTabWorkspaceSwitcher::new(evt_rx, config, sway_ipc, Box::new(clock.clone()));
+            Self {
+                switcher,
+                sway: sway.clone(),
+                clock,
+                evt_tx,
+            }
+        }
+
+        fn send(&mut self, evt: WorkspaceSwitcherEvent) {
+            self.switcher.handle_event(evt);
+            self.deliver_sway_events();
+        }
+
+        // Like the run loop, with the event queued in the channel
+        fn receive(&mut self, evt: WorkspaceSwitcherEvent) {
+            self.evt_tx.send(evt).unwrap();
+            let evt = self.switcher.next_event();
+            self.send(evt);
+        }
+
+        fn deliver_sway_events(&mut self) {
+            loop {
+                let event = self.sway.0.lock().unwrap().events.pop_front();
+                let Some(event) = event else {
+                    return;
+                };
+                self.switcher.handle_event(event);
+            }
+        }
+
+        fn mru(&self) -> Vec<&str> {
+            self.switcher
+                .mru_workspaces
+                .iter()
+                .map(|&id| match self.switcher.virtual_entry(id) {
+                    Some(entry) => &entry.label,
+                    None => self.switcher.workspace_name(id),
+                })
+                .collect()
+        }
+
+        fn focused(&self) -> String {
+            let state = self.sway.0.lock().unwrap();
+            let focused = state.workspaces.iter().find(|ws| ws.id == state.focused);
+            focused.unwrap().name.clone()
+        }
+    }
+
+    const ONE_OUTPUT: [(&str, &str); 3] = [("1", "eDP-1"), ("2", "eDP-1"), ("3", "eDP-1")];
+    const FOUR_WORKSPACES: [(&str, &str); 4] = [
+        ("1", "eDP-1"),
+        ("2", "eDP-1"),
+        ("3", "eDP-1"),
+        ("4", "eDP-1"),
+    ];
+
+    #[test]
+    fn preview_switches_only_on_commit() {
+        let sway = FakeSway::new(&ONE_OUTPUT);
+        let mut harness = Harness::new(
+            &sway,
+            SwitcherConfig {
+                preview: true,
+                ..config()
+            },
+        );
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        assert_eq!(sway.take_commands(), Vec::<String>::new());
+        assert_eq!(harness.focused(), "1");
+
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(sway.take_commands(), ["workspace 3"]);
+        assert_eq!(harness.mru(), ["3", "1", "2"]);
+        assert_eq!(harness.switcher.tab_count, 0);
+    }
+
+    #[test]
+    fn sequence_times_out_by_the_clock() {
+        let sway = FakeSway::new(&ONE_OUTPUT);
+        let mut harness = Harness::new(
+            &sway,
+            SwitcherConfig {
+                sequence_timeout: Some(Duration::from_secs(2)),
+                ..config()
+            },
+        );
+        harness.receive(WorkspaceSwitcherEvent::Trigger);
+        harness.clock.advance(Duration::from_millis(1500));
+        harness.receive(WorkspaceSwitcherEvent::Trigger);
+        assert_eq!(harness.switcher.tab_count, 2);
+
+        // Counted from the last trigger, the sway events don't restart it
+        let time_left = |harness: &Harness| harness.switcher.sequence_time_left();
+        assert_eq!(time_left(&harness), Some(Duration::from_secs(2)));
+        harness.clock.advance(Duration::from_millis(1500));
+        assert_eq!(time_left(&harness), Some(Duration::from_millis(500)));
+        harness.clock.advance(Duration::from_millis(500));
+        assert_eq!(time_left(&harness), Some(Duration::ZERO));
+        let evt = harness.switcher.next_event();
+        assert_eq!(format!("{evt:?}"), "EndMod");
+        harness.send(evt);
+        assert_eq!(harness.switcher.tab_count, 0);
+        assert_eq!(time_left(&harness), None);
+        assert_eq!(harness.mru(), ["3", "1", "2"]);
+    }
+
+    #[test]
+    fn creation_mode_follows_the_creation_order() {
+        let sway = FakeSway::new(&[
+            ("5", "eDP-1"),
+            ("1", "eDP-1"),
+            ("9", "eDP-1"),
+            ("2", "eDP-1"),
+        ]);
+        let mut harness = Harness::new(
+            &sway,
+            SwitcherConfig {
+                mode: CycleMode::Creation,
+                ..config()
+            },
+        );
+        sway.focus("2");
+        sway.focus("5");
+        harness.deliver_sway_events();
+        assert_eq!(harness.mru(), ["5", "2", "1", "9"]);
+
+        // The ids go up in the order the workspaces have been created in
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        assert_eq!(sway.take_commands(), ["workspace 1", "workspace 9"]);
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(sway.take_commands(), ["workspace 2", "workspace 5"]);
+
+        // From the middle of the creation order, wrapping around
+        sway.focus("9");
+        harness.deliver_sway_events();
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(sway.take_commands(), ["workspace 2", "workspace 5"]);
+        assert_eq!(harness.mru()[0], "5");
+    }
+
+    #[test]
+    fn external_focus_ends_the_sequence() {
+        let sway = FakeSway::new(&[
+            ("1", "eDP-1"),
+            ("2", "eDP-1"),
+            ("3", "eDP-1"),
+            ("4", "eDP-1"),
+        ]);
+        let mut harness = Harness::new(&sway, config());
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        sway.focus("4");
+        harness.deliver_sway_events();
+        assert_eq!(harness.switcher.tab_count, 0);
+        assert_eq!(harness.mru(), ["4", "1", "2", "3"]);
+
+        // The modifier release coming afterwards has nothing to commit
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(sway.take_commands(), ["workspace 2"]);
+        assert_eq!(harness.focused(), "4");
+        assert_eq!(harness.mru(), ["4", "1", "2", "3"]);
+    }
+
+    #[test]
+    fn external_focus_is_undone_with_ignore_external_focus() {
+        let sway = FakeSway::new(&[
+            ("1", "eDP-1"),
+            ("2", "eDP-1"),
+            ("3", "eDP-1"),
+            ("4", "eDP-1"),
+        ]);
+        let mut harness = Harness::new(
+            &sway,
+            SwitcherConfig {
+                ignore_external_focus: true,
+                ..config()
+            },
+        );
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        sway.focus("4");
+        harness.deliver_sway_events();
+        assert_eq!(harness.switcher.tab_count, 1);
+        assert_eq!(harness.focused(), "2");
+
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(
+            sway.take_commands(),
+            ["workspace 2", "workspace 2", "workspace 3"]
+        );
+        assert_eq!(harness.mru(), ["3", "1", "2", "4"]);
+    }
+
+    #[test]
+    fn pin_and_remove_outside_a_sequence_change_nothing() {
+        let sway = FakeSway::new(&[]);
+        let mut harness = Harness::new(&sway, config());
+        harness.send(WorkspaceSwitcherEvent::PinCurrent);
+        harness.send(WorkspaceSwitcherEvent::RemoveCurrent);
+        assert_eq!(harness.mru(), Vec::<&str>::new());
+
+        let sway = FakeSway::new(&FOUR_WORKSPACES);
+        let mut harness = Harness::new(&sway, config());
+        harness.send(WorkspaceSwitcherEvent::PinCurrent);
+        harness.send(WorkspaceSwitcherEvent::RemoveCurrent);
+        assert_eq!(harness.mru(), ["1", "2", "3", "4"]);
+        assert!(harness.switcher.pinned.is_empty());
+        assert!(harness.switcher.removed.is_empty());
+        assert_eq!(sway.take_commands(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn pinned_workspace_stays_right_after_the_focused_one() {
+        let sway = FakeSway::new(&FOUR_WORKSPACES);
+        let mut harness = Harness::new(&sway, config());
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::PinCurrent);
+        // The selection doesn't move until the sequence ends
+        assert_eq!(harness.mru(), ["1", "2", "3", "4"]);
+        assert_eq!(harness.switcher.tab_count, 2);
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(harness.mru(), ["4", "3", "1", "2"]);
+
+        for (name, mru) in [("2", ["2", "3", "4", "1"]), ("3", ["3", "2", "4", "1"])] {
+            sway.focus(name);
+            harness.deliver_sway_events();
+            assert_eq!(harness.mru(), mru);
+        }
+        sway.focus("1");
+        harness.deliver_sway_events();
+        assert_eq!(harness.mru(), ["1", "3", "2", "4"]);
+    }
+
+    #[test]
+    fn removed_workspace_is_back_once_focused() {
+        let sway = FakeSway::new(&FOUR_WORKSPACES);
+        let mut harness = Harness::new(&sway, config());
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::RemoveCurrent);
+        // The next workspace takes the place of the removed one
+        assert_eq!(harness.mru(), ["1", "3", "4"]);
+        assert_eq!(harness.switcher.tab_count, 1);
+        assert_eq!(harness.focused(), "3");
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(sway.take_commands(), ["workspace 2", "workspace 3"]);
+        assert_eq!(harness.mru(), ["3", "1", "4"]);
+
+        // Not listed again by the resync of the next sequence
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::Cancel);
+        assert_eq!(harness.mru(), ["3", "1", "4"]);
+
+        sway.focus("2");
+        harness.deliver_sway_events();
+        assert_eq!(harness.mru(), ["2", "3", "1", "4"]);
+        assert!(harness.switcher.removed.is_empty());
+    }
+
+    #[test]
+    fn new_workspaces_are_placed_by_the_position() {
+        let positions = [
+            (NewWorkspacePosition::Front, ["4", "1", "2", "3"]),
+            (NewWorkspacePosition::Second, ["1", "4", "2", "3"]),
+            (NewWorkspacePosition::Back, ["1", "2", "3", "4"]),
+        ];
+        for (new_workspace_position, mru) in positions {
+            let sway = FakeSway::new(&ONE_OUTPUT);
+            let mut harness = Harness::new(
+                &sway,
+                SwitcherConfig {
+                    new_workspace_position,
+                    ..config()
+                },
+            );
+            sway.create("4", "eDP-1");
+            harness.deliver_sway_events();
+            assert_eq!(harness.mru(), mru);
+
+            // Focusing it moves it to the front anyway
+            sway.focus("4");
+            harness.deliver_sway_events();
+            assert_eq!(harness.mru(), ["4", "1", "2", "3"]);
+        }
+    }
+
+    #[test]
+    fn new_workspace_is_cycled_to_by_the_position() {
+        // The list after the creation starts where the sequence starts, so the first trigger
+        // press goes to the second entry
+        let positions = [
+            (NewWorkspacePosition::Front, ["workspace 1", "workspace 2"]),
+            (NewWorkspacePosition::Second, ["workspace 4", "workspace 2"]),
+            (NewWorkspacePosition::Back, ["workspace 2", "workspace 3"]),
+        ];
+        for (new_workspace_position, commands) in positions {
+            let sway = FakeSway::new(&ONE_OUTPUT);
+            let mut harness = Harness::new(
+                &sway,
+                SwitcherConfig {
+                    new_workspace_position,
+                    ..config()
+                },
+            );
+            sway.create("4", "eDP-1");
+            harness.deliver_sway_events();
+            harness.send(WorkspaceSwitcherEvent::Trigger);
+            harness.send(WorkspaceSwitcherEvent::Trigger);
+            assert_eq!(sway.take_commands(), commands, "{new_workspace_position:?}");
+        }
+    }
+
+    #[test]
+    fn new_workspace_keeps_the_selection_and_the_start() {
+        let sway = FakeSway::new(&ONE_OUTPUT);
+        let mut harness = Harness::new(
+            &sway,
+            SwitcherConfig {
+                new_workspace_position: NewWorkspacePosition::Second,
+                ..config()
+            },
+        );
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        sway.create("4", "eDP-1");
+        harness.deliver_sway_events();
+        assert_eq!(harness.mru(), ["1", "4", "2", "3"]);
+        assert_eq!(harness.switcher.tab_count, 2);
+
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(harness.mru(), ["2", "1", "4", "3"]);
+
+        let sway = FakeSway::new(&[]);
+        let mut harness = Harness::new(
+            &sway,
+            SwitcherConfig {
+                new_workspace_position: NewWorkspacePosition::Second,
+                ..config()
+            },
+        );
+        sway.create("1", "eDP-1");
+        harness.deliver_sway_events();
+        assert_eq!(harness.mru(), ["1"]);
+    }
+
+    #[test]
+    fn switch_command_template_is_expanded() {
+        let sway = FakeSway::new(&[("1:mail", "eDP-1"), ("2:web", "eDP-1")]);
+        let mut harness = Harness::new(
+            &sway,
+            SwitcherConfig {
+                switch_command: "workspace --no-auto-back-and-forth number {num}; \
+                mark --add {name}-{id}"
+                    .to_string(),
+                ..config()
+            },
+        );
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        let id = sway.id("2:web");
+        assert_eq!(
+            sway.take_commands(),
+            [format!(
+                "workspace --no-auto-back-and-forth number 2; mark --add 2:web-{id}"
+            )]
+        );
+        assert_eq!(harness.focused(), "2:web");
+    }
+
+    #[test]
+    fn failed_switch_is_logged_and_the_sequence_goes_on() {
+        let sway = FakeSway::new(&ONE_OUTPUT);
+        sway.fail("workspace 2");
+        let mut harness = Harness::new(&sway, config());
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        assert_eq!(harness.focused(), "1");
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(sway.take_commands(), ["workspace 2", "workspace 3"]);
+        assert_eq!(harness.focused(), "3");
+        assert_eq!(harness.mru(), ["3", "1", "2"]);
+    }
+
+    #[test]
+    fn scratchpad_never_enters_the_list() {
+        // The fake sway's tree has the __i3 output with the __i3_scratch workspace
+        let sway = FakeSway::new(&ONE_OUTPUT);
+        let tree = sway.get_tree_json();
+        assert_eq!(tree["nodes"][0]["name"], "__i3");
+        assert_eq!(tree["nodes"][0]["nodes"][0]["name"], "__i3_scratch");
+
+        let mut harness = Harness::new(&sway, config());
+        assert_eq!(harness.mru(), ["1", "2", "3"]);
+        let mut scratch = node(3, "__i3_scratch", "workspace", 0, vec![]);
+        scratch["output"] = json!("__i3");
+        for change in ["init", "focus"] {
+            let event = json!({"change": change, "current": scratch, "old": null});
+            let event = Box::new(serde_json::from_value(event).unwrap());
+            harness.send(WorkspaceSwitcherEvent::SwayWsEvent(event));
+        }
+        assert_eq!(harness.mru(), ["1", "2", "3"]);
+
+        // Neither the resync at the start of a sequence nor cycling all the way around finds it
+        for _ in 0..4 {
+            harness.send(WorkspaceSwitcherEvent::Trigger);
+            assert!(!harness.switcher.mru_workspaces.contains(&3));
+        }
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(
+            sway.take_commands(),
+            ["workspace 2", "workspace 3", "workspace 1", "workspace 2"]
+        );
+        assert_eq!(harness.mru(), ["2", "1", "3"]);
+    }
+
+    const TWO_OUTPUTS: [(&str, &str); 4] = [
+        ("1", "eDP-1"),
+        ("2", "eDP-1"),
+        ("3", "HDMI-A-1"),
+        ("4", "HDMI-A-1"),
+    ];
+
+    #[test]
+    fn workspaces_on_excluded_outputs_are_skipped() {
+        let sway = FakeSway::new(&TWO_OUTPUTS);
+        let mut harness = Harness::new(
+            &sway,
+            SwitcherConfig {
+                excluded_outputs: vec!["HDMI-A-1".to_string()],
+                ..config()
+            },
+        );
+        assert_eq!(harness.mru(), ["1", "2"]);
+        sway.create("5", "HDMI-A-1");
+        harness.deliver_sway_events();
+        for _ in 0..3 {
+            harness.send(WorkspaceSwitcherEvent::Trigger);
+        }
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(
+            sway.take_commands(),
+            ["workspace 2", "workspace 1", "workspace 2"]
+        );
+        assert_eq!(harness.mru(), ["2", "1"]);
+
+        // Focusing an excluded output isn't recorded, the list goes on from where it was
+        sway.focus("4");
+        harness.deliver_sway_events();
+        assert_eq!(harness.mru(), ["2", "1"]);
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(sway.take_commands(), ["workspace 1"]);
+        assert_eq!(harness.focused(), "1");
+        assert_eq!(harness.mru(), ["1", "2"]);
+    }
+
+    #[test]
+    fn per_output_cycling_stays_on_the_focused_output() {
+        let sway = FakeSway::new(&TWO_OUTPUTS);
+        let mut harness = Harness::new(
+            &sway,
+            SwitcherConfig {
+                per_output: true,
+                ..config()
+            },
+        );
+        for _ in 0..3 {
+            harness.send(WorkspaceSwitcherEvent::Trigger);
+        }
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(
+            sway.take_commands(),
+            ["workspace 2", "workspace 1", "workspace 2"]
+        );
+        assert_eq!(harness.mru(), ["2", "1", "3", "4"]);
+    }
+
+    #[test]
+    fn per_output_order_is_kept_when_moving_between_outputs() {
+        let sway = FakeSway::new(&TWO_OUTPUTS);
+        let mut harness = Harness::new(
+            &sway,
+            SwitcherConfig {
+                per_output: true,
+                ..config()
+            },
+        );
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(sway.take_commands(), ["workspace 2"]);
+
+        // Over to the other output, only its workspaces are cycled there
+        sway.focus("3");
+        harness.deliver_sway_events();
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(sway.take_commands(), ["workspace 4", "workspace 3"]);
+        assert_eq!(harness.focused(), "3");
+
+        // And back to the workspace visible there, the trigger goes to the one used before it
+        // on that output, not to the other output's
+        sway.focus("2");
+        harness.deliver_sway_events();
+        assert_eq!(harness.mru(), ["2", "3", "4", "1"]);
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(sway.take_commands(), ["workspace 1"]);
+        assert_eq!(harness.mru(), ["1", "2", "3", "4"]);
+    }
+
+    #[test]
+    fn ignored_workspaces_are_skipped() {
+        let sway = FakeSway::new(&[
+            ("1", "eDP-1"),
+            ("mail", "eDP-1"),
+            ("2", "eDP-1"),
+            ("bg-music", "eDP-1"),
+        ]);
+        let mut harness = Harness::new(
+            &sway,
+            SwitcherConfig {
+                ignored_workspaces: ["^mail$", "^bg-.*"]
+                    .map(|re| regex::Regex::new(re).unwrap())
+                    .to_vec(),
+                ..config()
+            },
+        );
+        assert_eq!(harness.mru(), ["1", "2"]);
+        sway.create("bg-chat", "eDP-1");
+        harness.deliver_sway_events();
+        for _ in 0..3 {
+            harness.send(WorkspaceSwitcherEvent::Trigger);
+        }
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(
+            sway.take_commands(),
+            ["workspace 2", "workspace 1", "workspace 2"]
+        );
+        assert_eq!(harness.mru(), ["2", "1"]);
+
+        // Focusing one isn't recorded either
+        sway.focus("mail");
+        harness.deliver_sway_events();
+        assert_eq!(harness.mru(), ["2", "1"]);
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(sway.take_commands(), ["workspace 1"]);
+        assert_eq!(harness.mru(), ["1", "2"]);
+    }
+
+    #[test]
+    fn saved_order_is_restored() {
+        let dir = std::env::temp_dir().join(format!("swaywm-alttab-{}", std::process::id()));
+        let path = dir.join("state.json");
+        // The focused workspace stays first, the ones which don't exist anymore are skipped
+        let saved = ["2", "gone", "4", "1", "3"].map(String::from);
+        state::save(&path, &saved).unwrap();
+        let sway = FakeSway::new(&[
+            ("1", "eDP-1"),
+            ("3", "eDP-1"),
+            ("4", "eDP-1"),
+            ("2", "eDP-1"),
+        ]);
+        let mut harness = Harness::new(
+            &sway,
+            SwitcherConfig {
+                state_file: Some(path.clone()),
+                ..config()
+            },
+        );
+        assert_eq!(harness.mru(), ["1", "2", "4", "3"]);
+
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(sway.take_commands(), ["workspace 2"]);
+        assert_eq!(state::load(&path).unwrap(), ["2", "1", "4", "3"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn virtual_entry_runs_its_command_instead_of_switching() {
+        let sway = FakeSway::new(&ONE_OUTPUT);
+        let entry = VirtualEntry {
+            label: "scratchpad".to_string(),
+            command: "scratchpad show".to_string(),
+        };
+        let mut harness = Harness::new(
+            &sway,
+            SwitcherConfig {
+                virtual_entries: vec![entry],
+                preview: true,
+                ..config()
+            },
+        );
+        assert_eq!(harness.mru(), ["1", "2", "3", "scratchpad"]);
+        for _ in 0..3 {
+            harness.send(WorkspaceSwitcherEvent::Trigger);
+        }
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(sway.take_commands(), ["scratchpad show"]);
+        assert_eq!(harness.focused(), "1");
+        // The focused workspace stays in front
+        assert_eq!(harness.mru(), ["1", "scratchpad", "2", "3"]);
+    }
+
+    #[test]
+    fn vanished_workspace_is_resynced() {
+        let sway = FakeSway::new(&ONE_OUTPUT);
+        let mut harness = Harness::new(&sway, config());
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        // Gone without an event during the sequence, the last one left stays selected
+        let mut state = sway.0.lock().unwrap();
+        state.workspaces.retain(|ws| ws.name != "3");
+        drop(state);
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        assert_eq!(harness.mru(), ["1", "2"]);
+        assert_eq!(harness.switcher.tab_count, 1);
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(sway.take_commands(), ["workspace 2"]);
+        assert_eq!(harness.focused(), "2");
+        assert_eq!(harness.mru(), ["2", "1"]);
+    }
+
+    #[test]
+    fn cursor_is_warped_only_when_the_output_changes() {
+        let sway = FakeSway::new(&TWO_OUTPUTS);
+        let mut harness = Harness::new(
+            &sway,
+            SwitcherConfig {
+                warp_cursor: true,
+                ..config()
+            },
+        );
+        let mut sequence = |triggers| {
+            for _ in 0..triggers {
+                harness.send(WorkspaceSwitcherEvent::Trigger);
+            }
+            harness.send(WorkspaceSwitcherEvent::EndMod);
+            sway.take_commands()
+        };
+        assert_eq!(sequence(1), ["workspace 2"]);
+        // To the center of HDMI-A-1, which is right of eDP-1
+        assert_eq!(
+            sequence(2),
+            ["workspace 1", "workspace 3", "seat - cursor set 2880 540"]
+        );
+        // Through the other output and back
+        assert_eq!(sequence(3), ["workspace 2", "workspace 1", "workspace 4"]);
+        assert_eq!(
+            sequence(2),
+            ["workspace 3", "workspace 2", "seat - cursor set 960 540"]
+        );
+    }
+
+    fn boundary_harness(sway: &FakeSway, boundary: Boundary) -> Harness {
+        Harness::new(
+            sway,
+            SwitcherConfig {
+                boundary,
+                ..config()
+            },
+        )
+    }
+
+    #[test]
+    fn wrap_goes_around_at_both_ends() {
+        let sway = FakeSway::new(&ONE_OUTPUT);
+        let mut harness = boundary_harness(&sway, Boundary::Wrap);
+        for _ in 0..3 {
+            harness.send(WorkspaceSwitcherEvent::Trigger);
+        }
+        // Back at the start, as if nothing has been selected
+        assert_eq!(harness.switcher.tab_count, 0);
+        harness.send(WorkspaceSwitcherEvent::TriggerBack);
+        assert_eq!(harness.switcher.tab_count, 2);
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(
+            sway.take_commands(),
+            ["workspace 2", "workspace 3", "workspace 1", "workspace 3"]
+        );
+        assert_eq!(harness.mru(), ["3", "1", "2"]);
+    }
+
+    #[test]
+    fn clamp_stays_at_both_ends() {
+        let sway = FakeSway::new(&ONE_OUTPUT);
+        let mut harness = boundary_harness(&sway, Boundary::Clamp);
+        harness.send(WorkspaceSwitcherEvent::TriggerBack);
+        assert_eq!(harness.switcher.tab_count, 0);
+        for _ in 0..3 {
+            harness.send(WorkspaceSwitcherEvent::Trigger);
+        }
+        assert_eq!(harness.switcher.tab_count, 2);
+        for _ in 0..3 {
+            harness.send(WorkspaceSwitcherEvent::TriggerBack);
+        }
+        assert_eq!(harness.switcher.tab_count, 0);
+        assert_eq!(
+            sway.take_commands(),
+            ["workspace 2", "workspace 3", "workspace 2", "workspace 1"]
+        );
+    }
+
+    #[test]
+    fn clamp_flash_notifies_at_both_ends() {
+        let sway = FakeSway::new(&ONE_OUTPUT);
+        let notifier = RecordingNotifier::default();
+        let mut harness = Harness::new(
+            &sway,
+            SwitcherConfig {
+                boundary: Boundary::ClampFlash,
+                notifier: Box::new(notifier.clone()),
+                ..config()
+            },
+        );
+        let notified = || std::mem::take(&mut *notifier.0.lock().unwrap());
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        assert_eq!(notified(), Vec::<String>::new());
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        assert_eq!(harness.switcher.tab_count, 2);
+        assert_eq!(notified(), ["boundary"]);
+        harness.send(WorkspaceSwitcherEvent::TriggerBack);
+        harness.send(WorkspaceSwitcherEvent::TriggerBack);
+        assert_eq!(notified(), Vec::<String>::new());
+        harness.send(WorkspaceSwitcherEvent::TriggerBack);
+        assert_eq!(harness.switcher.tab_count, 0);
+        assert_eq!(notified(), ["boundary"]);
+        assert_eq!(
+            sway.take_commands(),
+            ["workspace 2", "workspace 3", "workspace 2", "workspace 1"]
+        );
+    }
+
+    #[test]
+    fn removing_the_last_entry_follows_the_boundary() {
+        // The last entry is selected with TriggerBack in the wrap mode, with Trigger twice in
+        // the clamp mode
+        let cases = [
+            (
+                Boundary::Wrap,
+                &[false][..],
+                &["workspace 3", "workspace 1"][..],
+                ["1", "2"],
+            ),
+            (
+                Boundary::Clamp,
+                &[true, true],
+                &["workspace 2", "workspace 3", "workspace 2"],
+                ["2", "1"],
+            ),
+        ];
+        for (boundary, triggers, commands, mru) in cases {
+            let sway = FakeSway::new(&ONE_OUTPUT);
+            let mut harness = boundary_harness(&sway, boundary);
+            for &forward in triggers {
+                harness.send(match forward {
+                    true => WorkspaceSwitcherEvent::Trigger,
+                    false => WorkspaceSwitcherEvent::TriggerBack,
+                });
+            }
+            assert_eq!(harness.switcher.tab_count, 2);
+            harness.send(WorkspaceSwitcherEvent::RemoveCurrent);
+            harness.send(WorkspaceSwitcherEvent::EndMod);
+            assert_eq!(sway.take_commands(), commands, "{boundary:?}");
+            assert_eq!(harness.mru(), mru, "{boundary:?}");
+        }
+    }
+
+    #[test]
+    fn only_committed_switches_are_announced() {
+        let sway = FakeSway::new(&FOUR_WORKSPACES);
+        let notifier = RecordingNotifier::default();
+        let mut harness = Harness::new(
+            &sway,
+            SwitcherConfig {
+                notifier: Box::new(notifier.clone()),
+                ..config()
+            },
+        );
+        let notified = || std::mem::take(&mut *notifier.0.lock().unwrap());
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        assert_eq!(notified(), Vec::<String>::new());
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(notified(), ["2"]);
+
+        // Ended by a focus change made by something else
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        sway.focus("4");
+        harness.deliver_sway_events();
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(notified(), Vec::<String>::new());
+
+        // Cancelled, or nothing selected
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::Cancel);
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(notified(), Vec::<String>::new());
+
+        assert_eq!(harness.mru(), ["4", "2", "1", "3"]);
+        harness.send(WorkspaceSwitcherEvent::JumpTo(3));
+        assert_eq!(notified(), ["3"]);
+    }
+
+    #[test]
+    fn windows_are_cycled_in_the_focus_order() {
+        let sway = FakeSway::new(&ONE_OUTPUT);
+        for (title, workspace) in [("a", "1"), ("b", "2"), ("c", "1")] {
+            sway.open_window(title, workspace);
+        }
+        let mut harness = Harness::new(
+            &sway,
+            SwitcherConfig {
+                mode: CycleMode::Windows,
+                ..config()
+            },
+        );
+        harness.deliver_sway_events();
+        // The windows of a workspace are listed together
+        assert_eq!(harness.mru(), ["a", "c", "b"]);
+
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        let focus = |title| format!("[con_id={}] focus", sway.window_id(title));
+        assert_eq!(sway.take_commands(), [focus("c"), focus("b")]);
+        assert_eq!(harness.mru(), ["b", "a", "c"]);
+        assert_eq!(harness.focused(), "2");
+
+        harness.send(WorkspaceSwitcherEvent::Trigger);
+        harness.send(WorkspaceSwitcherEvent::EndMod);
+        assert_eq!(sway.take_commands(), [focus("a")]);
+        assert_eq!(harness.mru(), ["a", "b", "c"]);
     }
 }
@@ -0,0 +1,155 @@
+// Command templates with {placeholder} substitution
+
+// A command split into arguments like a shell would do it, the placeholders are substituted
+// in each argument separately, so the values can't inject additional arguments or commands
+#[derive(Clone, Debug)]
+pub struct CommandTemplate {
+    argv: Vec<String>,
+}
+
+impl CommandTemplate {
+    pub fn parse(command: &str, known_placeholders: &[&str]) -> Result<Self, String> {
+        let argv = split_args(command)?;
+        if argv.is_empty() {
+            return Err("the command is empty".to_string());
+        }
+        for arg in &argv {
+            check_placeholders(arg, known_placeholders)?;
+        }
+        Ok(Self { argv })
+    }
+
+    pub fn expand(&self, values: &[(&str, &str)]) -> Vec<String> {
+        self.argv.iter().map(|arg| expand(arg, values)).collect()
+    }
+}
+
+// Makes sure all {...} placeholders in the template are known
+pub fn check_placeholders(template: &str, known: &[&str]) -> Result<(), String> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed placeholder in \"{template}\""))?;
+        let name = &rest[start + 1..start + end];
+        if !known.contains(&name) {
+            return Err(format!(
+                "unknown placeholder {{{name}}}, expected one of: {}",
+                known
+                    .iter()
+                    .map(|p| format!("{{{p}}}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        rest = &rest[start + end + 1..];
+    }
+    Ok(())
+}
+
+// The template is scanned once, so the values are taken literally even if they contain
+// placeholders themselves (e.g. a workspace named "{output}")
+pub fn expand(template: &str, values: &[(&str, &str)]) -> String {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            break;
+        };
+        expanded.push_str(&rest[..start]);
+        let name = &rest[start + 1..end];
+        match values.iter().find(|&&(placeholder, _)| placeholder == name) {
+            Some((_, value)) => expanded.push_str(value),
+            None => expanded.push_str(&rest[start..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+// Splits a command into arguments, supporting 'single' and "double" quotes and backslash escapes
+fn split_args(command: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut current: Option<String> = None;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                args.extend(current.take());
+            }
+            '\'' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => return Err("unclosed single quote".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => arg.push(c),
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => return Err("unclosed double quote".to_string()),
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err("unclosed double quote".to_string()),
+                    }
+                }
+            }
+            '\\' => {
+                let c = chars.next().ok_or("trailing backslash")?;
+                current.get_or_insert_with(String::new).push(c);
+            }
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(current);
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_substituted_once() {
+        let values = [("workspace", "{output}"), ("output", "eDP-1")];
+        assert_eq!(
+            expand("{workspace} on {output}", &values),
+            "{output} on eDP-1"
+        );
+        let values = [("output", "{workspace}"), ("workspace", "1")];
+        assert_eq!(expand("{output}/{workspace}", &values), "{workspace}/1");
+    }
+
+    #[test]
+    fn unknown_and_unclosed_braces_stay() {
+        let values = [("name", "1")];
+        assert_eq!(expand("{name} {other} {name", &values), "1 {other} {name");
+    }
+
+    #[test]
+    fn values_stay_in_their_arguments() {
+        let template = CommandTemplate::parse(
+            "notify-send 'on {output}' {workspace}",
+            &["workspace", "output"],
+        )
+        .unwrap();
+        let values = [("workspace", "a b; {output}"), ("output", "eDP-1")];
+        assert_eq!(
+            template.expand(&values),
+            ["notify-send", "on eDP-1", "a b; {output}"]
+        );
+    }
+}
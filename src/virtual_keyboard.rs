@@ -0,0 +1,332 @@
+// A minimal Wayland client for the virtual keyboard protocol (zwp_virtual_keyboard_v1), so
+// the forwarded keys are injected through the compositor instead of a uinput device. Only the
+// few requests needed to create the keyboard and send keys are implemented, to avoid pulling
+// in the Wayland libraries.
+
+use std::error::Error;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+use evdev_rs::enums::{EventCode, EV_KEY};
+use evdev_rs::InputEvent;
+
+use crate::output::EventSink;
+
+// Object ids are allocated by the client, there's a fixed set of them
+const DISPLAY_ID: u32 = 1;
+const REGISTRY_ID: u32 = 2;
+const SEAT_ID: u32 = 3;
+const MANAGER_ID: u32 = 4;
+const KEYBOARD_ID: u32 = 5;
+const FIRST_CALLBACK_ID: u32 = 6;
+
+const KEYMAP_FORMAT_XKB_V1: u32 = 1;
+
+// The real modifiers, which come first in any XKB keymap
+const SHIFT: u32 = 1 << 0;
+const LOCK: u32 = 1 << 1;
+const CONTROL: u32 = 1 << 2;
+const MOD1: u32 = 1 << 3;
+const MOD2: u32 = 1 << 4;
+const MOD4: u32 = 1 << 6;
+
+struct Message {
+    object: u32,
+    opcode: u16,
+    args: Vec<u8>,
+}
+
+impl Message {
+    fn new(object: u32, opcode: u16) -> Self {
+        Self {
+            object,
+            opcode,
+            args: Vec::new(),
+        }
+    }
+
+    fn uint(mut self, value: u32) -> Self {
+        self.args.extend(value.to_ne_bytes());
+        self
+    }
+
+    fn string(mut self, value: &str) -> Self {
+        self = self.uint(value.len() as u32 + 1);
+        self.args.extend(value.as_bytes());
+        self.args.push(0);
+        self.args.resize(self.args.len().next_multiple_of(4), 0);
+        self
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let size = 8 + self.args.len() as u32;
+        let mut bytes = Vec::with_capacity(size as usize);
+        bytes.extend(self.object.to_ne_bytes());
+        bytes.extend((size << 16 | self.opcode as u32).to_ne_bytes());
+        bytes.extend(&self.args);
+        bytes
+    }
+}
+
+// Reads the arguments of an event one by one
+struct Args<'a>(&'a [u8]);
+
+impl Args<'_> {
+    fn uint(&mut self) -> u32 {
+        let Some((value, rest)) = self.0.split_first_chunk() else {
+            return 0;
+        };
+        self.0 = rest;
+        u32::from_ne_bytes(*value)
+    }
+
+    fn string(&mut self) -> String {
+        let len = self.uint() as usize;
+        let padded = len.next_multiple_of(4).min(self.0.len());
+        let value = &self.0[..len.min(padded)];
+        self.0 = &self.0[padded..];
+        String::from_utf8_lossy(value.strip_suffix(&[0]).unwrap_or(value)).into_owned()
+    }
+}
+
+pub struct VirtualKeyboard {
+    socket: UnixStream,
+    next_callback_id: u32,
+    // Modifier keys held down and the locked modifiers, the compositor doesn't derive them
+    // from the keys of virtual keyboards
+    modifiers_held: Vec<EV_KEY>,
+    locked: u32,
+}
+
+impl VirtualKeyboard {
+    pub fn connect() -> Result<Self, Box<dyn Error>> {
+        let display = std::env::var_os("WAYLAND_DISPLAY").ok_or("WAYLAND_DISPLAY is not set")?;
+        let mut path = PathBuf::from(display);
+        if path.is_relative() {
+            let runtime_dir =
+                std::env::var_os("XDG_RUNTIME_DIR").ok_or("XDG_RUNTIME_DIR is not set")?;
+            path = PathBuf::from(runtime_dir).join(path);
+        }
+        let socket = UnixStream::connect(&path).map_err(|e| {
+            format!(
+                "can't connect to the Wayland display ({}): {e}",
+                path.display()
+            )
+        })?;
+
+        let mut keyboard = Self {
+            socket,
+            next_callback_id: FIRST_CALLBACK_ID,
+            modifiers_held: Vec::new(),
+            locked: 0,
+        };
+        keyboard.send(Message::new(DISPLAY_ID, 1).uint(REGISTRY_ID))?;
+        let (seat, manager) = keyboard.find_globals()?;
+        keyboard.send(
+            Message::new(REGISTRY_ID, 0)
+                .uint(seat)
+                .string("wl_seat")
+                .uint(1)
+                .uint(SEAT_ID),
+        )?;
+        keyboard.send(
+            Message::new(REGISTRY_ID, 0)
+                .uint(manager)
+                .string("zwp_virtual_keyboard_manager_v1")
+                .uint(1)
+                .uint(MANAGER_ID),
+        )?;
+        keyboard.send(Message::new(MANAGER_ID, 0).uint(SEAT_ID).uint(KEYBOARD_ID))?;
+        keyboard.send_keymap()?;
+        // The compositor may refuse to create the keyboard, that's reported as an error
+        keyboard.roundtrip(|_, _, _| {})?;
+        Ok(keyboard)
+    }
+
+    // Returns the global names of the seat and the virtual keyboard manager
+    fn find_globals(&mut self) -> Result<(u32, u32), Box<dyn Error>> {
+        let (mut seat, mut manager) = (None, None);
+        self.roundtrip(|object, opcode, mut args| {
+            if (object, opcode) != (REGISTRY_ID, 0) {
+                return;
+            }
+            let name = args.uint();
+            match args.string().as_str() {
+                "wl_seat" => {
+                    seat.get_or_insert(name);
+                }
+                "zwp_virtual_keyboard_manager_v1" => manager = Some(name),
+                _ => {}
+            }
+        })?;
+        let seat = seat.ok_or("the compositor has no seat")?;
+        let manager = manager.ok_or("the compositor doesn't support virtual keyboards")?;
+        Ok((seat, manager))
+    }
+
+    // Waits until the compositor has processed all the previous requests, passing the events
+    // received meanwhile to the handler
+    fn roundtrip(&mut self, mut handle: impl FnMut(u32, u16, Args)) -> Result<(), Box<dyn Error>> {
+        let callback_id = self.next_callback_id;
+        self.next_callback_id += 1;
+        self.send(Message::new(DISPLAY_ID, 0).uint(callback_id))?;
+        loop {
+            let (object, opcode, body) = self.read_event()?;
+            match (object, opcode) {
+                (DISPLAY_ID, 0) => {
+                    let mut args = Args(&body);
+                    let (_, code, message) = (args.uint(), args.uint(), args.string());
+                    return Err(format!("Wayland protocol error {code}: {message}").into());
+                }
+                (id, 0) if id == callback_id => return Ok(()),
+                _ => handle(object, opcode, Args(&body)),
+            }
+        }
+    }
+
+    fn read_event(&mut self) -> std::io::Result<(u32, u16, Vec<u8>)> {
+        let mut header = [0u8; 8];
+        self.socket.read_exact(&mut header)?;
+        let object = u32::from_ne_bytes(header[..4].try_into().expect("4 bytes"));
+        let size_opcode = u32::from_ne_bytes(header[4..].try_into().expect("4 bytes"));
+        let mut body = vec![0u8; ((size_opcode >> 16) as usize).saturating_sub(8)];
+        self.socket.read_exact(&mut body)?;
+        Ok((object, size_opcode as u16, body))
+    }
+
+    fn send(&mut self, message: Message) -> std::io::Result<()> {
+        self.socket.write_all(&message.to_bytes())
+    }
+
+    // The keymap is built from the same XKB_DEFAULT_* variables sway uses when the config
+    // doesn't set the layout, the US layout otherwise
+    fn keymap() -> String {
+        let layout = std::env::var("XKB_DEFAULT_LAYOUT").unwrap_or_else(|_| "us".to_string());
+        let layout = layout.split(',').next().unwrap_or("us");
+        let symbols = match std::env::var("XKB_DEFAULT_VARIANT") {
+            Ok(variant) if !variant.is_empty() => format!("{layout}({variant})"),
+            _ => layout.to_string(),
+        };
+        format!(
+            "xkb_keymap {{\n\
+            \txkb_keycodes {{ include \"evdev+aliases(qwerty)\" }};\n\
+            \txkb_types {{ include \"complete\" }};\n\
+            \txkb_compat {{ include \"complete\" }};\n\
+            \txkb_symbols {{ include \"pc+{symbols}+inet(evdev)\" }};\n\
+            }};\n"
+        )
+    }
+
+    // The keymap is passed as a file descriptor along with the request
+    fn send_keymap(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut keymap = Self::keymap().into_bytes();
+        keymap.push(0);
+
+        let fd = unsafe { libc::memfd_create(c"swaywm-alttab-keymap".as_ptr(), libc::MFD_CLOEXEC) };
+        if fd < 0 {
+            let e = std::io::Error::last_os_error();
+            return Err(format!("can't create the keymap file: {e}").into());
+        }
+        let mut file = std::fs::File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+        file.write_all(&keymap)
+            .map_err(|e| format!("can't write the keymap file: {e}"))?;
+
+        let message = Message::new(KEYBOARD_ID, 0)
+            .uint(KEYMAP_FORMAT_XKB_V1)
+            .uint(keymap.len() as u32);
+        self.send_with_fd(&message.to_bytes(), file.as_raw_fd())
+            .map_err(|e| format!("can't send the keymap: {e}").into())
+    }
+
+    fn send_with_fd(&self, bytes: &[u8], fd: RawFd) -> std::io::Result<()> {
+        let fd_size = std::mem::size_of::<RawFd>() as u32;
+        let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fd_size) } as usize];
+        let mut iov = libc::iovec {
+            iov_base: bytes.as_ptr() as *mut libc::c_void,
+            iov_len: bytes.len(),
+        };
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = control.len() as _;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(fd_size) as _;
+            std::ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>(), fd);
+        }
+
+        let sent = unsafe { libc::sendmsg(self.socket.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) };
+        match sent {
+            n if n < 0 => Err(std::io::Error::last_os_error()),
+            n if n as usize != bytes.len() => Err(std::io::Error::other("short write")),
+            _ => Ok(()),
+        }
+    }
+
+    fn modifier_mask(key: EV_KEY) -> Option<u32> {
+        match key {
+            EV_KEY::KEY_LEFTSHIFT | EV_KEY::KEY_RIGHTSHIFT => Some(SHIFT),
+            EV_KEY::KEY_LEFTCTRL | EV_KEY::KEY_RIGHTCTRL => Some(CONTROL),
+            EV_KEY::KEY_LEFTALT | EV_KEY::KEY_RIGHTALT => Some(MOD1),
+            EV_KEY::KEY_LEFTMETA | EV_KEY::KEY_RIGHTMETA => Some(MOD4),
+            _ => None,
+        }
+    }
+
+    // Updates the modifier state with the key, returns whether it has changed
+    fn update_modifiers(&mut self, key: EV_KEY, pressed: bool) -> bool {
+        match (key, pressed) {
+            (EV_KEY::KEY_CAPSLOCK, true) => self.locked ^= LOCK,
+            (EV_KEY::KEY_NUMLOCK, true) => self.locked ^= MOD2,
+            (key, pressed) if Self::modifier_mask(key).is_some() => {
+                self.modifiers_held.retain(|&held| held != key);
+                if pressed {
+                    self.modifiers_held.push(key);
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl EventSink for VirtualKeyboard {
+    // Only key presses and releases are sent, the compositor does the autorepeat itself
+    fn write_event(&mut self, ev: &InputEvent) -> std::io::Result<()> {
+        let (EventCode::EV_KEY(key), 0 | 1) = (ev.event_code, ev.value) else {
+            return Ok(());
+        };
+        let time_ms = ev.time.tv_sec as u64 * 1000 + ev.time.tv_usec as u64 / 1000;
+        self.send(
+            Message::new(KEYBOARD_ID, 1)
+                .uint(time_ms as u32)
+                .uint(key as u32)
+                .uint(ev.value as u32),
+        )?;
+
+        if self.update_modifiers(key, ev.value == 1) {
+            let depressed = self
+                .modifiers_held
+                .iter()
+                .filter_map(|&key| Self::modifier_mask(key))
+                .fold(0, |mask, modifier| mask | modifier);
+            self.send(
+                Message::new(KEYBOARD_ID, 2)
+                    .uint(depressed)
+                    .uint(0)
+                    .uint(self.locked)
+                    .uint(0),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        "Wayland virtual keyboard".to_string()
+    }
+}
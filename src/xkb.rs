@@ -0,0 +1,210 @@
+// Keysym lookup in the actual keymap with libxkbcommon. The library is loaded at runtime, so
+// it isn't needed to build or run the app, the US layout table is used without it. The keymap
+// is compiled from the xkb_* settings of the sway config, falling back to the same
+// XKB_DEFAULT_* variables sway uses when the config doesn't set them.
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::sync::OnceLock;
+
+use evdev_rs::enums::{int_to_ev_key, EV_KEY};
+
+const LIBRARY: &CStr = c"libxkbcommon.so.0";
+// XKB keycodes are evdev keycodes shifted by this
+const EVDEV_OFFSET: u32 = 8;
+
+type KeysymFromName = unsafe extern "C" fn(*const c_char, c_int) -> u32;
+
+// The keymap settings, the ones left out come from the XKB_DEFAULT_* variables
+#[derive(Debug, Default)]
+pub struct RuleNames {
+    pub layout: Option<String>,
+    pub variant: Option<String>,
+    pub options: Option<String>,
+}
+
+// struct xkb_rule_names, NULL fields are the defaults
+#[repr(C)]
+struct RawRuleNames {
+    rules: *const c_char,
+    model: *const c_char,
+    layout: *const c_char,
+    variant: *const c_char,
+    options: *const c_char,
+}
+
+impl RuleNames {
+    // Takes the first xkb_layout and xkb_variant of the config and all of its xkb_options, the
+    // keysyms are the same on all keyboards anyway
+    pub fn from_sway_config(config: &str) -> Self {
+        let options = config_values(config, "xkb_options");
+        Self {
+            layout: config_values(config, "xkb_layout")
+                .first()
+                .map(|s| s.to_string()),
+            variant: config_values(config, "xkb_variant")
+                .first()
+                .map(|s| s.to_string()),
+            options: (!options.is_empty()).then(|| options.join(",")),
+        }
+    }
+}
+
+// Collects the values of an input setting from the sway config text, e.g. `us,de` from
+// `input type:keyboard xkb_layout us,de` or from the same line in an input block
+pub fn config_values<'a>(config: &'a str, setting: &str) -> Vec<&'a str> {
+    config
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or(""))
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            words.find(|&w| w == setting)?;
+            words.next()
+        })
+        .map(|value| value.trim_matches(['"', '\'', '}']))
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+// The rule names from the running sway's config, the defaults if it can't be reached
+fn sway_rule_names() -> RuleNames {
+    let config = crate::connect_ipc().and_then(|mut conn| Ok(conn.get_config()?.config));
+    match config {
+        Ok(config) => RuleNames::from_sway_config(&config),
+        Err(e) => {
+            log::debug!("Can't get the keymap settings from sway, using the defaults: {e}");
+            RuleNames::default()
+        }
+    }
+}
+
+pub struct Keymap {
+    keysym_from_name: KeysymFromName,
+    // (keysym, key), keysyms on lower shift levels first
+    keys: Vec<(u32, EV_KEY)>,
+}
+
+// Loaded once on the first use, None if libxkbcommon or the keymap isn't available
+pub fn keymap() -> Option<&'static Keymap> {
+    static KEYMAP: OnceLock<Option<Keymap>> = OnceLock::new();
+    KEYMAP
+        .get_or_init(|| match unsafe { Keymap::load(&sway_rule_names()) } {
+            Ok(keymap) => Some(keymap),
+            Err(e) => {
+                log::debug!("Resolving keysyms with the US layout: {e}");
+                None
+            }
+        })
+        .as_ref()
+}
+
+impl Keymap {
+    // Returns the key producing the keysym in the first layout of the keymap
+    pub fn key_for(&self, name: &str) -> Option<EV_KEY> {
+        let name = CString::new(name).ok()?;
+        let keysym = unsafe { (self.keysym_from_name)(name.as_ptr(), 0) };
+        if keysym == 0 {
+            return None;
+        }
+        self.keys
+            .iter()
+            .find(|&&(sym, _)| sym == keysym)
+            .map(|&(_, key)| key)
+    }
+
+    pub unsafe fn load(names: &RuleNames) -> Result<Self, String> {
+        let lib = libc::dlopen(LIBRARY.as_ptr(), libc::RTLD_NOW);
+        if lib.is_null() {
+            return Err(format!("can't load {}", LIBRARY.to_string_lossy()));
+        }
+        let symbol = |name: &CStr| {
+            let sym = libc::dlsym(lib, name.as_ptr());
+            match sym.is_null() {
+                true => Err(format!("no {} in libxkbcommon", name.to_string_lossy())),
+                false => Ok(sym),
+            }
+        };
+
+        let context_new: unsafe extern "C" fn(c_int) -> *mut c_void =
+            std::mem::transmute(symbol(c"xkb_context_new")?);
+        let context_unref: unsafe extern "C" fn(*mut c_void) =
+            std::mem::transmute(symbol(c"xkb_context_unref")?);
+        let keymap_new_from_names: unsafe extern "C" fn(
+            *mut c_void,
+            *const c_void,
+            c_int,
+        ) -> *mut c_void = std::mem::transmute(symbol(c"xkb_keymap_new_from_names")?);
+        let keymap_unref: unsafe extern "C" fn(*mut c_void) =
+            std::mem::transmute(symbol(c"xkb_keymap_unref")?);
+        let min_keycode: unsafe extern "C" fn(*mut c_void) -> u32 =
+            std::mem::transmute(symbol(c"xkb_keymap_min_keycode")?);
+        let max_keycode: unsafe extern "C" fn(*mut c_void) -> u32 =
+            std::mem::transmute(symbol(c"xkb_keymap_max_keycode")?);
+        let num_levels: unsafe extern "C" fn(*mut c_void, u32, u32) -> u32 =
+            std::mem::transmute(symbol(c"xkb_keymap_num_levels_for_key")?);
+        let syms_by_level: unsafe extern "C" fn(
+            *mut c_void,
+            u32,
+            u32,
+            u32,
+            *mut *const u32,
+        ) -> c_int = std::mem::transmute(symbol(c"xkb_keymap_key_get_syms_by_level")?);
+        let keysym_from_name: KeysymFromName =
+            std::mem::transmute(symbol(c"xkb_keysym_from_name")?);
+
+        let to_c_string = |name: &Option<String>| {
+            name.as_deref()
+                .map(CString::new)
+                .transpose()
+                .map_err(|_| "a keymap setting contains a NUL byte".to_string())
+        };
+        let (layout, variant, options) = (
+            to_c_string(&names.layout)?,
+            to_c_string(&names.variant)?,
+            to_c_string(&names.options)?,
+        );
+        let c_ptr = |name: &Option<CString>| name.as_ref().map_or(std::ptr::null(), |s| s.as_ptr());
+        let context = context_new(0);
+        if context.is_null() {
+            return Err("can't create an XKB context".to_string());
+        }
+        let raw_names = RawRuleNames {
+            rules: std::ptr::null(),
+            model: std::ptr::null(),
+            layout: c_ptr(&layout),
+            variant: c_ptr(&variant),
+            options: c_ptr(&options),
+        };
+        let keymap = keymap_new_from_names(
+            context,
+            &raw_names as *const RawRuleNames as *const c_void,
+            0,
+        );
+        if keymap.is_null() {
+            context_unref(context);
+            return Err("can't compile the keymap".to_string());
+        }
+
+        let mut keys = Vec::new();
+        for keycode in min_keycode(keymap)..=max_keycode(keymap) {
+            let Some(key) = keycode.checked_sub(EVDEV_OFFSET).and_then(int_to_ev_key) else {
+                continue;
+            };
+            for level in 0..num_levels(keymap, keycode, 0) {
+                let mut syms = std::ptr::null();
+                let count = syms_by_level(keymap, keycode, 0, level, &mut syms);
+                for i in 0..count.max(0) as usize {
+                    keys.push((level, *syms.add(i), key));
+                }
+            }
+        }
+        keymap_unref(keymap);
+        context_unref(context);
+
+        // Stable, so on the same level the lower keycode wins
+        keys.sort_by_key(|&(level, _, _)| level);
+        Ok(Self {
+            keysym_from_name,
+            keys: keys.into_iter().map(|(_, sym, key)| (sym, key)).collect(),
+        })
+    }
+}